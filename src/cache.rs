@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The last subscription payload that was fetched and parsed successfully.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SubscriptionCache {
+    pub url: String,
+    pub fetched_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo: Option<String>,
+    pub payload: String,
+}
+
+impl SubscriptionCache {
+    pub fn new(url: &str, payload: String) -> Self {
+        Self {
+            url: url.to_string(),
+            fetched_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            etag: None,
+            last_modified: None,
+            userinfo: None,
+            payload,
+        }
+    }
+
+    /// Load the cache of the subscription `name`, if it was fetched from `url`.
+    pub fn load(name: &str, url: &str) -> Option<Self> {
+        let buf = std::fs::read(cache_file(name)?).ok()?;
+        serde_json::from_slice::<Self>(buf.as_ref())
+            .ok()
+            .filter(|cache| cache.url == url)
+    }
+
+    pub fn save(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let file = cache_file(name).ok_or("config dir not found")?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(file, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Minutes elapsed since the payload was fetched.
+    pub fn age_minutes(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().saturating_sub(self.fetched_at) / 60)
+            .unwrap_or(0)
+    }
+}
+
+fn cache_file(name: &str) -> Option<PathBuf> {
+    let file_name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    dirs::config_dir().map(|conf| {
+        conf.join("v2ray-maid")
+            .join("subscriptions")
+            .join(format!("{}.json", file_name))
+    })
+}
@@ -0,0 +1,233 @@
+use crate::vlink::{Protocol, StreamSecurity, Transport, TransportParams, VLink};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClashConfig {
+    #[serde(default = "Vec::new")]
+    pub proxies: Vec<serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ClashProxy {
+    pub name: String,
+    pub r#type: String,
+    pub server: String,
+    pub port: u16,
+    pub uuid: Option<String>,
+    #[serde(rename = "alterId")]
+    pub alter_id: Option<i32>,
+    pub cipher: Option<String>,
+    pub password: Option<String>,
+    pub flow: Option<String>,
+    pub tls: Option<bool>,
+    pub sni: Option<String>,
+    pub servername: Option<String>,
+    pub skip_cert_verify: Option<bool>,
+    pub alpn: Option<Vec<String>>,
+    pub client_fingerprint: Option<String>,
+    pub network: Option<String>,
+    pub ws_path: Option<String>,
+    pub ws_headers: Option<HashMap<String, String>>,
+    pub ws_opts: Option<ClashWsOpts>,
+    pub h2_opts: Option<ClashH2Opts>,
+    pub http_opts: Option<ClashHttpOpts>,
+    pub grpc_opts: Option<ClashGrpcOpts>,
+    pub plugin: Option<String>,
+    pub plugin_opts: Option<ClashPluginOpts>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClashWsOpts {
+    pub path: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClashH2Opts {
+    pub host: Option<Vec<String>>,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClashHttpOpts {
+    pub path: Option<Vec<String>>,
+    pub headers: Option<HashMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ClashGrpcOpts {
+    pub grpc_service_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClashPluginOpts {
+    pub mode: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub tls: Option<bool>,
+}
+
+impl TryFrom<ClashProxy> for VLink {
+    type Error = String;
+
+    fn try_from(proxy: ClashProxy) -> Result<Self, Self::Error> {
+        let protocol = match proxy.r#type.as_str() {
+            "vmess" => Protocol::Vmess {
+                id: proxy.uuid.ok_or("missing uuid")?,
+                alter_id: proxy.alter_id.unwrap_or(0),
+                security: proxy.cipher.unwrap_or_else(|| "auto".to_string()),
+            },
+            "vless" => Protocol::Vless {
+                id: proxy.uuid.ok_or("missing uuid")?,
+                encryption: "none".to_string(),
+                flow: proxy.flow.unwrap_or_default(),
+            },
+            "trojan" => Protocol::Trojan {
+                password: proxy.password.ok_or("missing password")?,
+            },
+            "ss" => {
+                let plugin = match proxy.plugin {
+                    Some(plugin) if plugin == "v2ray-plugin" => {
+                        let opts = proxy.plugin_opts.ok_or("missing plugin-opts")?;
+                        let mut plugin = format!(
+                            "v2ray-plugin;mode={}",
+                            opts.mode.as_deref().unwrap_or("websocket")
+                        );
+                        if let Some(host) = opts.host {
+                            plugin.push_str(format!(";host={}", host).as_str());
+                        }
+                        if let Some(path) = opts.path {
+                            plugin.push_str(format!(";path={}", path).as_str());
+                        }
+                        if opts.tls.unwrap_or(false) {
+                            plugin.push_str(";tls");
+                        }
+                        plugin
+                    }
+                    Some(plugin) => plugin,
+                    None => String::new(),
+                };
+                let (transport, stream_security) = Transport::from_ss_plugin(plugin.as_str())?;
+                return Ok(VLink {
+                    protocol: Protocol::Shadowsocks {
+                        method: proxy.cipher.ok_or("missing cipher")?,
+                        password: proxy.password.ok_or("missing password")?,
+                        plugin,
+                    },
+                    address: proxy.server,
+                    port: proxy.port,
+                    remarks: proxy.name,
+                    transport,
+                    stream_security,
+                    ..VLink::default()
+                });
+            }
+            t => return Err(format!("unsupported type {}", t)),
+        };
+
+        let mut params = TransportParams::default();
+        if let Some(network) = proxy.network {
+            match network.as_str() {
+                "ws" => {
+                    let (path, headers) = match proxy.ws_opts {
+                        Some(opts) => (opts.path, opts.headers),
+                        None => (proxy.ws_path, proxy.ws_headers),
+                    };
+                    params.path = path.unwrap_or_default();
+                    params.host = headers
+                        .and_then(|headers| {
+                            headers
+                                .into_iter()
+                                .find(|(k, _)| k.eq_ignore_ascii_case("host"))
+                                .map(|(_, v)| v)
+                        })
+                        .unwrap_or_default();
+                    params.network = network;
+                }
+                "h2" => {
+                    if let Some(opts) = proxy.h2_opts {
+                        params.host = opts.host.unwrap_or_default().join(",");
+                        params.path = opts.path.unwrap_or_default();
+                    }
+                    params.network = network;
+                }
+                "http" => {
+                    if let Some(opts) = proxy.http_opts {
+                        params.path = opts.path.unwrap_or_default().join(",");
+                        params.host = opts
+                            .headers
+                            .and_then(|headers| {
+                                headers
+                                    .into_iter()
+                                    .find(|(k, _)| k.eq_ignore_ascii_case("host"))
+                                    .map(|(_, v)| v.join(","))
+                            })
+                            .unwrap_or_default();
+                    }
+                    params.network = "tcp".to_string();
+                    params.header_type = "http".to_string();
+                }
+                "grpc" => {
+                    if let Some(opts) = proxy.grpc_opts {
+                        params.path = opts.grpc_service_name.unwrap_or_default();
+                    }
+                    params.network = network;
+                }
+                _ => params.network = network,
+            }
+        }
+
+        let tls = proxy.tls.unwrap_or(false) || matches!(protocol, Protocol::Trojan { .. });
+        Ok(VLink {
+            protocol,
+            address: proxy.server,
+            port: proxy.port,
+            remarks: proxy.name,
+            transport: Transport::try_from(params)?,
+            stream_security: if tls {
+                StreamSecurity::Tls
+            } else {
+                StreamSecurity::None
+            },
+            sni: proxy.sni.or(proxy.servername).unwrap_or_default(),
+            allow_insecure: proxy.skip_cert_verify.unwrap_or(false),
+            alpn: proxy.alpn.unwrap_or_default(),
+            fingerprint: proxy.client_fingerprint.unwrap_or_default(),
+            ..VLink::default()
+        })
+    }
+}
+
+/// Whether `text` looks like a Clash configuration rather than a list of share links.
+pub fn is_clash(text: &str) -> bool {
+    text.lines().any(|line| line.starts_with("proxies:"))
+}
+
+/// Map every supported entry of the `proxies` list of a Clash configuration onto a `VLink`.
+pub fn parse(text: &str) -> Result<Vec<VLink>, Box<dyn std::error::Error>> {
+    let mut v = Vec::new();
+    let mut errors = 0;
+
+    let config = serde_yaml::from_str::<ClashConfig>(text)?;
+    for proxy in config.proxies {
+        let vlink = serde_yaml::from_value::<ClashProxy>(proxy.clone())
+            .map_err(|err| err.to_string())
+            .and_then(VLink::try_from);
+        match vlink {
+            Ok(vlink) => v.push(vlink),
+            Err(err) => {
+                debug!("{:?} 解析失败：{}", proxy, err);
+                errors += 1;
+            }
+        }
+    }
+    if errors > 0 {
+        warn!("{} 个 Clash 代理解析失败", errors);
+    }
+    Ok(v)
+}
@@ -1,6 +1,9 @@
 #![feature(async_closure)]
 
+mod cache;
+mod clash;
 mod ping;
+mod selector;
 mod settings;
 mod subscription;
 mod utils;
@@ -9,12 +12,14 @@ mod v2ray_object;
 mod v2ray_template;
 mod vlink;
 
+use crate::selector::Selector;
 use crate::settings::AppSettings;
 use crate::utils::pick_free_tcp_port;
 use crate::v2ray_ctl::V2rayApp;
 use crate::v2ray_object::{Port, V2rayObject};
+use crate::v2ray_template::{GlobalSettings, Template};
 use crate::vlink::VLink;
-use log::info;
+use log::{error, info, warn};
 use std::option::Option::Some;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
@@ -39,19 +44,89 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let ctl: Arc<V2rayApp> = v2ray_ctl::init(settings.program.as_str()).into();
 
     let mut proxies = settings.proxies.clone().unwrap_or(Vec::new());
+    let exports = settings.exports.clone().unwrap_or_default();
+
+    let mut errors = Vec::new();
+    let remark_rules: Vec<_> = settings
+        .remark_rules
+        .iter()
+        .flatten()
+        .filter_map(|rule| match regex::Regex::new(rule.pattern.as_str()) {
+            Ok(re) => Some((re, rule.replace.clone())),
+            Err(err) => {
+                errors.push(format!("remark_rules `{}`: {}", rule.pattern, err));
+                None
+            }
+        })
+        .collect();
+    let mut compile = |name: String, filter| match Selector::compile(filter) {
+        Ok(selector) => Some(selector),
+        Err(errs) => {
+            errors.extend(errs.into_iter().map(|err| format!("{} {}", name, err)));
+            None
+        }
+    };
+    let proxy_selectors: Vec<_> = proxies
+        .iter()
+        .enumerate()
+        .map(|(i, p)| compile(format!("proxies[{}]", i), &p.filter))
+        .collect();
+    let export_selectors: Vec<_> = exports
+        .iter()
+        .enumerate()
+        .map(|(i, e)| compile(format!("exports[{}]", i), &e.filter))
+        .collect();
+    if !errors.is_empty() {
+        for err in &errors {
+            error!("无效的正则表达式：{}", err);
+        }
+        return Err(format!("invalid patterns: {}", errors.join("; ")).into());
+    }
+
+    let global_settings: Arc<GlobalSettings> = GlobalSettings {
+        template: match settings.template.as_ref() {
+            Some(file) => Some(Template::load(file.as_str()).map_err(|err| {
+                error!("无效的模板：{}，{}", file, err);
+                err
+            })?),
+            None => None,
+        },
+        ..GlobalSettings::default().with(settings.outbound.as_ref())
+    }
+    .into();
 
     {
-        let subs = subscription::fetch(settings.sub_url.as_str()).await?;
-        let subs = parallel_test_latency(subs, &ctl, &settings).await;
-        for proxy in &mut proxies {
-            let regex = regex::Regex::new(proxy.selector.as_str());
+        let mut subs =
+            subscription::fetch_all(&settings.subscriptions, settings.quota_warn.as_ref(), &ctl)
+                .await;
+        for vlink in &mut subs {
+            vlink.apply_remark_rules(&remark_rules);
+        }
+        let len = subs.len();
+        let subs = VLink::dedup(subs);
+        if subs.len() < len {
+            info!("合并了 {} 个重复的服务器", len - subs.len());
+        }
+        let subs = parallel_test_latency(subs, &ctl, &settings, &global_settings).await;
+        for (export, selector) in exports.iter().zip(export_selectors.iter().flatten()) {
+            let mut vlinks: Vec<VLink> = subs
+                .iter()
+                .filter(|vlink| selector.is_match(vlink))
+                .cloned()
+                .collect();
+            vlinks.sort_by_key(|vlink| vlink.latency);
+            vlinks.truncate(export.limit.unwrap_or(vlinks.len()));
 
+            std::fs::write(export.target_file.as_str(), subscription::encode(&vlinks))?;
+            info!(
+                "已导出 {} 个服务器到：{}",
+                vlinks.len(),
+                export.target_file.as_str()
+            );
+        }
+        for (proxy, selector) in proxies.iter_mut().zip(proxy_selectors.iter().flatten()) {
             for vlink in &subs {
-                if regex
-                    .as_ref()
-                    .map(|re| re.is_match(vlink.remarks.as_str()))
-                    .unwrap_or(false)
-                {
+                if selector.is_match(vlink) {
                     proxy.vlinks.push(vlink.clone());
                 }
             }
@@ -72,14 +147,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     for proxy in &proxies {
         let vlink = proxy.vlinks.first();
+        let tag = proxy.tag.as_ref().map(|s| s.as_str()).unwrap_or("proxy");
         if let Some(v) = vlink {
             info!(
                 "『{}』 最快的服务器是 『{}』，延迟 {} ms",
-                proxy.selector, v.remarks, v.latency
+                tag, v.remarks, v.latency
             );
 
-            let tag = proxy.tag.as_ref().map(|s| s.as_str()).unwrap_or("proxy");
-
             let mut v2ray_object = serde_json::from_slice::<V2rayObject>(
                 std::fs::read(proxy.target_file.as_str())?.as_ref(),
             )?;
@@ -92,13 +166,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 v2ray_object.outbounds.as_mut().unwrap()
             };
 
+            let global_settings = global_settings.with(proxy.outbound.as_ref());
             let limit = proxy.limit.unwrap_or(1);
             if limit == 1 {
-                let outbound = v.gen_outbound(&tag, None);
+                let outbound = v.gen_outbound(&tag, Some(&global_settings))?;
                 outbounds.insert(0, outbound);
             } else {
                 for (i, v) in proxy.vlinks.iter().enumerate() {
-                    let outbound = v.gen_outbound(format!("{}_{}", tag, i).as_str(), None);
+                    let outbound =
+                        v.gen_outbound(format!("{}_{}", tag, i).as_str(), Some(&global_settings))?;
                     outbounds.insert(0, outbound);
                     if i == limit {
                         break;
@@ -116,7 +192,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 v.remarks.as_str()
             );
         } else {
-            info!("『{}』 没有可用的服务器", proxy.selector);
+            info!("『{}』 没有可用的服务器", tag);
         }
     }
     Ok(())
@@ -126,14 +202,32 @@ async fn parallel_test_latency<'a>(
     subs: Vec<VLink>,
     ctl: &Arc<V2rayApp>,
     settings: &Arc<AppSettings>,
+    global_settings: &Arc<GlobalSettings>,
 ) -> Vec<VLink> {
-    async fn test_latency(vlink: &VLink, ctl: &V2rayApp, settings: &AppSettings) -> i32 {
-        let mut v2ray_config = vlink.gen_full(None);
+    async fn test_latency(
+        vlink: &VLink,
+        ctl: &V2rayApp,
+        settings: &AppSettings,
+        global_settings: &GlobalSettings,
+    ) -> i32 {
+        let mut v2ray_config = match vlink.gen_full(Some(global_settings)) {
+            Ok(v2ray_config) => v2ray_config,
+            Err(err) => {
+                warn!("『{}』生成配置失败：{}", vlink.remarks, err);
+                return -1;
+            }
+        };
         let listen_port = pick_free_tcp_port();
         let inbound = v2ray_config.inbounds.as_mut().unwrap().first_mut().unwrap();
         inbound.port = Port::Int(listen_port);
         let v2ray_json = serde_json::to_string(&v2ray_config).unwrap();
-        let process = ctl.start(v2ray_json.as_str()).unwrap();
+        let process = match ctl.start(v2ray_json.as_str()) {
+            Ok(process) => process,
+            Err(err) => {
+                warn!("『{}』启动 V2ray 失败：{}", vlink.remarks, err);
+                return -1;
+            }
+        };
         let latency = ping::google(
             vlink.remarks.as_str(),
             Some(format!("http://127.0.0.1:{}", listen_port).as_str()),
@@ -165,6 +259,7 @@ async fn parallel_test_latency<'a>(
     for _ in 0..concurrency {
         let ctl = ctl.clone();
         let settings = settings.clone();
+        let global_settings = global_settings.clone();
         let in_subs = in_subs.clone();
         let out_subs = out_subs.clone();
         let thread = tokio::spawn(async move {
@@ -173,7 +268,7 @@ async fn parallel_test_latency<'a>(
                 let vlink = subs.pop();
                 vlink
             } {
-                vlink.latency = test_latency(&vlink, &ctl, &settings).await;
+                vlink.latency = test_latency(&vlink, &ctl, &settings, &global_settings).await;
                 if vlink.latency > 0 {
                     let mut subs = out_subs.lock().unwrap();
                     subs.push(vlink);
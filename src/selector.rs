@@ -0,0 +1,123 @@
+use crate::settings::VlinkFilter;
+use crate::vlink::VLink;
+use regex::Regex;
+
+/// The node field a pattern is matched against, chosen with a `field:`
+/// prefix; patterns without a known prefix match the remarks.
+#[derive(Debug, Clone)]
+enum Field {
+    Remarks,
+    Source,
+    Address,
+    Port,
+    Network,
+    Protocol,
+}
+
+#[derive(Debug, Clone)]
+struct FieldPattern {
+    field: Field,
+    re: Regex,
+}
+
+impl FieldPattern {
+    fn compile(pattern: &str) -> Result<Self, regex::Error> {
+        let (field, re) = match pattern.split_once(':') {
+            Some(("remarks", re)) => (Field::Remarks, re),
+            Some(("source", re)) => (Field::Source, re),
+            Some(("address", re)) => (Field::Address, re),
+            Some(("port", re)) => (Field::Port, re),
+            Some(("network", re)) => (Field::Network, re),
+            Some(("protocol", re)) => (Field::Protocol, re),
+            _ => (Field::Remarks, pattern),
+        };
+        Ok(Self {
+            field,
+            re: Regex::new(re)?,
+        })
+    }
+
+    fn is_match(&self, vlink: &VLink) -> bool {
+        match self.field {
+            Field::Remarks => vlink.is_match(&self.re),
+            Field::Source => std::iter::once(&vlink.source)
+                .chain(vlink.aliases.iter().map(|(source, _)| source))
+                .any(|source| self.re.is_match(source.as_str())),
+            Field::Address => self.re.is_match(vlink.address.as_str()),
+            Field::Port => self.re.is_match(vlink.port.to_string().as_str()),
+            Field::Network => self.re.is_match(vlink.transport.network()),
+            Field::Protocol => self.re.is_match(vlink.protocol.name()),
+        }
+    }
+}
+
+/// A compiled `VlinkFilter`: a node is selected when it matches any
+/// include pattern (or there are none), no exclude pattern and every tag.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    include: Vec<FieldPattern>,
+    exclude: Vec<FieldPattern>,
+    tags: Vec<(String, Regex)>,
+}
+
+impl Selector {
+    /// Compile `filter`, listing every invalid pattern on failure.
+    pub fn compile(filter: &VlinkFilter) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut compile = |kind: &str, patterns: &mut dyn Iterator<Item = &String>| {
+            patterns
+                .filter_map(|pattern| match FieldPattern::compile(pattern.as_str()) {
+                    Ok(p) => Some(p),
+                    Err(err) => {
+                        errors.push(format!("{} `{}`: {}", kind, pattern, err));
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let include = compile(
+            "include",
+            &mut filter
+                .selector
+                .iter()
+                .filter(|s| !s.is_empty())
+                .chain(filter.include.iter().flatten()),
+        );
+        let exclude = compile("exclude", &mut filter.exclude.iter().flatten());
+
+        let tags = filter
+            .tags
+            .iter()
+            .flatten()
+            .filter_map(|(name, pattern)| match Regex::new(pattern.as_str()) {
+                Ok(re) => Some((name.clone(), re)),
+                Err(err) => {
+                    errors.push(format!("tags.{} `{}`: {}", name, pattern, err));
+                    None
+                }
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(Self {
+                include,
+                exclude,
+                tags,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn is_match(&self, vlink: &VLink) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.is_match(vlink)))
+            && !self.exclude.iter().any(|p| p.is_match(vlink))
+            && self.tags.iter().all(|(name, re)| {
+                vlink
+                    .tags
+                    .get(name)
+                    .map(|value| re.is_match(value.as_str()))
+                    .unwrap_or(false)
+            })
+    }
+}
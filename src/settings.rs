@@ -1,5 +1,6 @@
 use crate::vlink::VLink;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
 pub fn load_settings(file: &str) -> Option<AppSettings> {
@@ -18,6 +19,20 @@ pub fn load_settings(file: &str) -> Option<AppSettings> {
         .map(
             |buf| match serde_json::from_slice::<AppSettings>(buf.as_ref()) {
                 Ok(mut settings) => {
+                    if let Some(url) = settings.sub_url.take() {
+                        settings.subscriptions.insert(
+                            0,
+                            Subscription {
+                                name: "default".to_string(),
+                                url: Some(url),
+                                links: None,
+                                fetch_proxy: None,
+                                user_agent: None,
+                                headers: None,
+                                enabled: true,
+                            },
+                        );
+                    }
                     let cpu_num = num_cpus::get_physical();
                     settings.concurrency = match settings.concurrency {
                         Some(concurrency) if concurrency >= 1 => Some(concurrency),
@@ -33,26 +48,135 @@ pub fn load_settings(file: &str) -> Option<AppSettings> {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AppSettings {
-    pub sub_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_url: Option<String>,
+    #[serde(default = "Vec::new")]
+    pub subscriptions: Vec<Subscription>,
     #[serde(default = "default_loglevel")]
     pub loglevel: String,
     #[serde(default = "default_program")]
     pub program: String,
     pub ping_times: Option<i32>,
     pub proxies: Option<Vec<VlinkProxy>>,
+    pub exports: Option<Vec<VlinkExport>>,
     pub concurrency: Option<usize>,
+    pub quota_warn: Option<QuotaWarnSettings>,
+    pub remark_rules: Option<Vec<RemarkRule>>,
+    /// Path of a JSON file with merge patches for generated configs,
+    /// see `v2ray_template::Template`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<OutboundSettings>,
+}
+
+/// Options shared by every generated outbound; those of a proxy override
+/// the global ones.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct OutboundSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mux: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mux_concurrency: Option<i32>,
+    /// `sockopt.domainStrategy`, e.g. `UseIPv4`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_strategy: Option<String>,
+    /// `sockopt.mark`, the SO_MARK of outgoing connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mark: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_fast_open: Option<bool>,
+    /// User level of the generated outbounds, 8 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<i32>,
+}
+
+/// Rewrites node remarks matching `pattern` with `replace`, recording the
+/// named captures of `pattern` as tags of the node.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemarkRule {
+    pub pattern: String,
+    pub replace: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaWarnSettings {
+    pub remaining_percent: Option<f64>,
+    pub expire_days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Subscription {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<String>>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_proxy: Option<FetchProxy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// How a subscription is fetched when its host is not reachable directly:
+/// through an http/socks5 proxy url, or through an outbound of an existing
+/// v2ray config run by a temporary v2ray.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FetchProxy {
+    Url(String),
+    Outbound {
+        target_file: String,
+        tag: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct VlinkProxy {
-    pub selector: String,
+    #[serde(flatten)]
+    pub filter: VlinkFilter,
     pub tag: Option<String>,
     pub target_file: String,
     pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<OutboundSettings>,
     #[serde(skip_serializing, default = "Vec::new")]
     pub vlinks: Vec<VLink>,
 }
 
+/// Which nodes a proxy or export picks. Patterns are regexes matched
+/// against the remarks, or against another field when prefixed with
+/// `source:`, `address:`, `port:`, `network:` or `protocol:`.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct VlinkFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<HashMap<String, String>>,
+}
+
+/// A base64 subscription file republishing the healthy nodes picked by
+/// `filter`, fastest first.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VlinkExport {
+    #[serde(flatten)]
+    pub filter: VlinkFilter,
+    pub target_file: String,
+    pub limit: Option<usize>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
 fn default_loglevel() -> String {
     "info".to_string()
 }
@@ -1,72 +1,969 @@
-use crate::vlink::VLink;
+use crate::cache::SubscriptionCache;
+use crate::clash;
+use crate::settings::{FetchProxy, QuotaWarnSettings, Subscription};
+use crate::utils::{decode_base64, pick_free_tcp_port};
+use crate::v2ray_ctl::{V2rayApp, V2rayAppProcess};
+use crate::v2ray_object::{Port, V2rayObject};
+use crate::v2ray_template;
+use crate::vlink::{split_list, Protocol, StreamSecurity, Transport, TransportParams, VLink};
+use log::{debug, error, info, warn};
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Deserialize, Serialize)]
 struct VmessShare {
-    pub host: String,
-    pub path: String,
-    pub tls: String,
-    pub verify_cert: bool,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub v: String,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub ps: String,
     pub add: String,
+    #[serde(deserialize_with = "string_or_number")]
     pub port: u16,
+    pub id: String,
+    #[serde(default, deserialize_with = "string_or_number")]
     pub aid: i32,
+    #[serde(default)]
+    pub scy: String,
+    #[serde(default)]
     pub net: String,
+    #[serde(default)]
     pub r#type: String,
-    pub v: String,
-    pub ps: String,
-    pub id: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub tls: String,
+    #[serde(default)]
+    pub sni: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_cert: Option<bool>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub alpn: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub fp: String,
+    #[serde(default, deserialize_with = "string_or_number")]
     pub class: i32,
 }
 
-impl Into<VLink> for VmessShare {
-    fn into(self) -> VLink {
-        let mut vlink = VLink::default();
-        vlink.security = "auto".to_string();
-        vlink.network = "tcp".to_string();
-        vlink.header_type = "none".to_string();
+impl TryFrom<VmessShare> for VLink {
+    type Error = String;
 
-        vlink.remarks = self.ps;
-        vlink.address = self.add;
-        vlink.port = self.port;
-        vlink.id = self.id;
-        vlink.alter_id = self.aid;
-        vlink.network = self.net;
-        vlink.header_type = self.r#type;
-        vlink.request_host = self.host;
-        vlink.path = self.path;
-        vlink.stream_security = self.tls;
+    fn try_from(share: VmessShare) -> Result<Self, Self::Error> {
+        let mut params = TransportParams {
+            network: share.net,
+            header_type: share.r#type,
+            host: share.host,
+            path: share.path,
+        };
+        // Version 1 links carry the path in the host field as `host;path`.
+        if share.v.is_empty() || share.v == "1" {
+            if let Some((host, path)) = params.host.split_once(';') {
+                params.path = path.to_string();
+                params.host = host.to_string();
+            }
+        }
 
-        vlink
+        Ok(VLink {
+            protocol: Protocol::Vmess {
+                id: share.id,
+                alter_id: share.aid,
+                security: if share.scy.is_empty() {
+                    "auto".to_string()
+                } else {
+                    share.scy
+                },
+            },
+            address: share.add,
+            port: share.port,
+            remarks: share.ps,
+            transport: Transport::try_from(params)?,
+            stream_security: share.tls.parse()?,
+            sni: share.sni,
+            allow_insecure: share.verify_cert == Some(false),
+            alpn: split_list(share.alpn.as_str()),
+            fingerprint: share.fp,
+            ..VLink::default()
+        })
     }
 }
 
-#[allow(dead_code)]
-pub async fn fetch(url: &str) -> Result<Vec<VLink>, Box<dyn std::error::Error>> {
+impl TryFrom<&VLink> for VmessShare {
+    type Error = String;
+
+    fn try_from(vlink: &VLink) -> Result<Self, Self::Error> {
+        let (id, alter_id, security) = match &vlink.protocol {
+            Protocol::Vmess {
+                id,
+                alter_id,
+                security,
+            } => (id, alter_id, security),
+            protocol => return Err(format!("{} is not vmess", protocol.name())),
+        };
+        let params = TransportParams::from(&vlink.transport);
+        Ok(Self {
+            v: "2".to_string(),
+            ps: vlink.remarks.clone(),
+            add: vlink.address.clone(),
+            port: vlink.port,
+            id: id.clone(),
+            aid: *alter_id,
+            scy: security.clone(),
+            net: params.network,
+            r#type: params.header_type,
+            host: params.host,
+            path: params.path,
+            tls: match vlink.stream_security {
+                StreamSecurity::None => String::new(),
+                StreamSecurity::Tls => "tls".to_string(),
+            },
+            sni: vlink.sni.clone(),
+            verify_cert: if vlink.allow_insecure {
+                Some(false)
+            } else {
+                None
+            },
+            alpn: vlink.alpn.join(","),
+            fp: vlink.fingerprint.clone(),
+            class: 0,
+        })
+    }
+}
+
+/// Deserialize a field that providers write either as a JSON string or as
+/// a JSON number, treating an empty string as the default value.
+fn string_or_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr + Default,
+    T::Err: std::fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Int(i64),
+        Float(f64),
+    }
+
+    match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) if s.trim().is_empty() => Ok(T::default()),
+        StringOrNumber::String(s) => s.trim().parse::<T>(),
+        StringOrNumber::Int(n) => n.to_string().parse::<T>(),
+        StringOrNumber::Float(n) => n.to_string().parse::<T>(),
+    }
+    .map_err(serde::de::Error::custom)
+}
+
+/// A share link that could not be turned into a `VLink`.
+#[derive(Debug)]
+struct LinkError {
+    pub line: usize,
+    pub scheme: String,
+    pub reason: String,
+}
+
+/// Log how many share links were dropped, per scheme, with the reason of
+/// each failure at debug level.
+fn report_link_errors(errors: &[LinkError]) {
+    if errors.is_empty() {
+        return;
+    }
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for err in errors {
+        debug!(
+            "第 {} 行 {} 链接解析失败：{}",
+            err.line, err.scheme, err.reason
+        );
+        match counts.iter_mut().find(|(scheme, _)| *scheme == err.scheme) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((err.scheme.as_str(), 1)),
+        }
+    }
+    warn!(
+        "{} 个链接解析失败（{}）",
+        errors.len(),
+        counts
+            .iter()
+            .map(|(scheme, count)| format!("{}: {}", scheme, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// Parse a VMess share link (`vmess://base64(json)`).
+fn parse_vmess(link: &str) -> Result<VLink, Box<dyn std::error::Error>> {
+    let buf = decode_base64(&link["vmess://".len()..])?;
+    let vmess = serde_json::from_slice::<VmessShare>(&buf)?;
+    Ok(VLink::try_from(vmess)?)
+}
+
+/// Traffic quota and expiry reported by a provider in the
+/// `subscription-userinfo: upload=..; download=..; total=..; expire=..` header.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SubscriptionUserInfo {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    /// Unix timestamp in seconds, absent when the subscription never expires.
+    pub expire: Option<u64>,
+}
+
+impl FromStr for SubscriptionUserInfo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut userinfo = SubscriptionUserInfo::default();
+        for pair in s.split(';') {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            let value = value
+                .parse::<u64>()
+                .or_else(|_| value.parse::<f64>().map(|v| v as u64))
+                .map_err(|_| format!("invalid {} {}", key, value))?;
+            match key {
+                "upload" => userinfo.upload = value,
+                "download" => userinfo.download = value,
+                "total" => userinfo.total = value,
+                "expire" if value > 0 => userinfo.expire = Some(value),
+                _ => {}
+            }
+        }
+        Ok(userinfo)
+    }
+}
+
+impl SubscriptionUserInfo {
+    pub fn remaining(&self) -> u64 {
+        self.total.saturating_sub(self.upload + self.download)
+    }
+
+    /// Days left before the subscription expires, negative once it has.
+    pub fn remaining_days(&self) -> Option<i64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.expire
+            .map(|expire| (expire as i64 - now as i64).div_euclid(24 * 60 * 60))
+    }
+
+    /// Log the quota of the subscription `name`, warning when it runs
+    /// below the thresholds of `quota_warn`.
+    pub fn report(&self, name: &str, quota_warn: Option<&QuotaWarnSettings>) {
+        const GB: f64 = (1u64 << 30) as f64;
+        let remaining_days = self.remaining_days();
+        info!(
+            "订阅『{}』已用流量 {:.2} GB / {:.2} GB，{}",
+            name,
+            (self.upload + self.download) as f64 / GB,
+            self.total as f64 / GB,
+            remaining_days
+                .map(|days| format!("{} 天后到期", days))
+                .unwrap_or_else(|| "长期有效".to_string())
+        );
+
+        let percent = quota_warn.and_then(|w| w.remaining_percent).unwrap_or(10.0);
+        if self.total > 0 && (self.remaining() as f64) < self.total as f64 * percent / 100.0 {
+            warn!(
+                "订阅『{}』剩余流量 {:.2} GB，不足 {}%",
+                name,
+                self.remaining() as f64 / GB,
+                percent
+            );
+        }
+        let expire_days = quota_warn.and_then(|w| w.expire_days).unwrap_or(7);
+        if let Some(days) = remaining_days {
+            if days < 0 {
+                warn!("订阅『{}』已过期", name);
+            } else if days <= expire_days {
+                warn!("订阅『{}』将在 {} 天后过期", name, days);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Sip008 {
+    pub version: i32,
+    pub servers: Vec<Sip008Server>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Sip008Server {
+    pub id: Option<String>,
+    pub remarks: Option<String>,
+    pub server: String,
+    pub server_port: u16,
+    pub password: String,
+    pub method: String,
+    pub plugin: Option<String>,
+    pub plugin_opts: Option<String>,
+}
+
+impl TryFrom<Sip008Server> for VLink {
+    type Error = String;
+
+    fn try_from(server: Sip008Server) -> Result<Self, Self::Error> {
+        let plugin = match server.plugin.filter(|p| !p.is_empty()) {
+            Some(plugin) => match server.plugin_opts.filter(|o| !o.is_empty()) {
+                Some(opts) => format!("{};{}", plugin, opts),
+                None => plugin,
+            },
+            None => String::new(),
+        };
+        let (transport, stream_security) = Transport::from_ss_plugin(plugin.as_str())?;
+
+        Ok(VLink {
+            protocol: Protocol::Shadowsocks {
+                method: server.method,
+                password: server.password,
+                plugin,
+            },
+            address: server.server,
+            port: server.server_port,
+            remarks: server.remarks.or(server.id).unwrap_or_default(),
+            transport,
+            stream_security,
+            ..VLink::default()
+        })
+    }
+}
+
+/// `host` without the brackets around an IPv6 literal.
+fn unbracket(host: &str) -> &str {
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// `address` as the host of a URL, bracketing IPv6 literals.
+fn bracket(address: &str) -> String {
+    if address.contains(':') {
+        format!("[{}]", address)
+    } else {
+        address.to_string()
+    }
+}
+
+/// Parse a Shadowsocks share link, either SIP002
+/// (`ss://base64(method:password)@host:port/?plugin=..#remarks`,
+/// `ss://method:password@host:port#remarks`) or the legacy
+/// `ss://base64(method:password@host:port)#remarks` form.
+fn parse_ss(link: &str) -> Result<VLink, Box<dyn std::error::Error>> {
+    let link = &link["ss://".len()..];
+    let (link, remarks) = match link.split_once('#') {
+        Some((link, remarks)) => (link, percent_decode_str(remarks).decode_utf8()?.to_string()),
+        None => (link, String::new()),
+    };
+
+    if !link.contains('@') {
+        let decoded = String::from_utf8(decode_base64(link)?)?;
+        let (user_info, host_port) = decoded.rsplit_once('@').ok_or("missing server")?;
+        let (method, password) = user_info.split_once(':').ok_or("missing password")?;
+        let (host, port) = host_port.rsplit_once(':').ok_or("missing port")?;
+        return Ok(VLink {
+            protocol: Protocol::Shadowsocks {
+                method: method.to_string(),
+                password: password.to_string(),
+                plugin: String::new(),
+            },
+            address: unbracket(host).to_string(),
+            port: port.trim_end_matches('/').parse()?,
+            remarks,
+            transport: Transport::tcp(),
+            stream_security: StreamSecurity::None,
+            ..VLink::default()
+        });
+    }
+
+    // Standard base64 user info may contain `/`, which would end the
+    // authority of a URL, so only the part after it is parsed as one.
+    let (user_info, server) = link.rsplit_once('@').ok_or("missing server")?;
+    let url = url::Url::parse(format!("ss://{}", server).as_str())?;
+    let user_info = percent_decode_str(user_info).decode_utf8()?.to_string();
+    let user_info = if user_info.contains(':') {
+        user_info
+    } else {
+        String::from_utf8(decode_base64(user_info.as_str())?)?
+    };
+    let (method, password) = user_info
+        .split_once(':')
+        .map(|(method, password)| (method.to_string(), password.to_string()))
+        .ok_or("missing password")?;
+    let plugin = url
+        .query_pairs()
+        .find(|(k, _)| k == "plugin")
+        .map(|(_, plugin)| plugin.to_string())
+        .unwrap_or_default();
+    let (transport, stream_security) = Transport::from_ss_plugin(plugin.as_str())?;
+
+    Ok(VLink {
+        protocol: Protocol::Shadowsocks {
+            method,
+            password,
+            plugin,
+        },
+        address: unbracket(url.host_str().ok_or("missing host")?).to_string(),
+        port: url.port().ok_or("missing port")?,
+        remarks,
+        transport,
+        stream_security,
+        ..VLink::default()
+    })
+}
+
+/// Parse a URL style share link as used by Trojan and VLESS
+/// (`trojan://password@host:port?sni=..&type=ws&host=..&path=..#remarks`,
+/// `vless://uuid@host:port?encryption=none&security=tls&flow=..#remarks`).
+fn parse_url_link(link: &str) -> Result<VLink, Box<dyn std::error::Error>> {
+    let url = url::Url::parse(link)?;
+
+    let mut params = TransportParams::default();
+    let mut security = match url.scheme() {
+        "trojan" => "tls".to_string(),
+        _ => "none".to_string(),
+    };
+    let mut sni = String::new();
+    let mut allow_insecure = false;
+    let mut alpn = String::new();
+    let mut fingerprint = String::new();
+    let mut encryption = "none".to_string();
+    let mut flow = String::new();
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "sni" | "peer" => sni = value.to_string(),
+            "allowInsecure" => allow_insecure = value == "1" || value == "true",
+            "alpn" => alpn = value.to_string(),
+            "fp" => fingerprint = value.to_string(),
+            "type" => params.network = value.to_string(),
+            "security" => security = value.to_string(),
+            "host" | "quicSecurity" => params.host = value.to_string(),
+            "path" | "key" | "serviceName" => params.path = value.to_string(),
+            "headerType" | "mode" => params.header_type = value.to_string(),
+            "encryption" => encryption = value.to_string(),
+            "flow" => flow = value.to_string(),
+            _ => {}
+        }
+    }
+
+    let credential = percent_decode_str(url.username())
+        .decode_utf8()?
+        .to_string();
+    let protocol = match url.scheme() {
+        "trojan" => Protocol::Trojan {
+            password: credential,
+        },
+        "vless" => Protocol::Vless {
+            id: credential,
+            encryption,
+            flow,
+        },
+        scheme => return Err(format!("unsupported scheme {}", scheme).into()),
+    };
+
+    Ok(VLink {
+        protocol,
+        address: unbracket(url.host_str().ok_or("missing host")?).to_string(),
+        port: url.port().unwrap_or(443),
+        remarks: match url.fragment() {
+            Some(remarks) => percent_decode_str(remarks).decode_utf8()?.to_string(),
+            None => String::new(),
+        },
+        transport: Transport::try_from(params)?,
+        stream_security: security.parse()?,
+        sni,
+        allow_insecure,
+        alpn: split_list(alpn.as_str()),
+        fingerprint,
+        ..VLink::default()
+    })
+}
+
+/// Fetch every enabled subscription concurrently, recording on each
+/// `VLink` the name of the subscription it came from.
+pub async fn fetch_all(
+    subscriptions: &[Subscription],
+    quota_warn: Option<&QuotaWarnSettings>,
+    ctl: &Arc<V2rayApp>,
+) -> Vec<VLink> {
+    let tasks: Vec<_> = subscriptions
+        .iter()
+        .filter(|sub| sub.enabled)
+        .cloned()
+        .map(|sub| {
+            let ctl = ctl.clone();
+            tokio::spawn(async move {
+                let result = match (&sub.links, &sub.url) {
+                    (Some(links), _) => {
+                        parse_share_links(links.join("\n").as_str()).map(|vlinks| (vlinks, None))
+                    }
+                    (None, Some(url)) => fetch(&sub, url.as_str(), &ctl).await,
+                    (None, None) => Err("neither url nor links is set".into()),
+                }
+                .map_err(|e| e.to_string());
+                (sub, result)
+            })
+        })
+        .collect();
+
+    let mut v = Vec::new();
+    for task in tasks {
+        let (sub, result) = task.await.unwrap();
+        match result {
+            Ok((vlinks, userinfo)) => {
+                info!("订阅『{}』获取到 {} 个服务器", sub.name, vlinks.len());
+                if let Some(userinfo) = userinfo {
+                    userinfo.report(sub.name.as_str(), quota_warn);
+                }
+                v.extend(vlinks.into_iter().map(|mut vlink| {
+                    vlink.source = sub.name.clone();
+                    vlink
+                }));
+            }
+            Err(err) => error!("订阅『{}』获取失败：{}", sub.name, err),
+        }
+    }
+    v
+}
+
+/// Fetch a subscription from an http(s) url, or read a saved copy of it
+/// from a `file://` url, together with the traffic quota the provider
+/// reports in the `subscription-userinfo` header.
+///
+/// The last payload fetched over the network is cached under the config
+/// dir, revalidated with conditional requests, and used in place of the
+/// network response when the subscription endpoint cannot be reached.
+pub async fn fetch(
+    sub: &Subscription,
+    url: &str,
+    ctl: &Arc<V2rayApp>,
+) -> Result<(Vec<VLink>, Option<SubscriptionUserInfo>), Box<dyn std::error::Error>> {
+    let name = sub.name.as_str();
+    let parsed_url = url::Url::parse(url)?;
+    if parsed_url.scheme() == "file" {
+        let path = parsed_url
+            .to_file_path()
+            .map_err(|_| format!("invalid file url {}", url))?;
+        let text = tokio::fs::read(path).await?;
+        return Ok((parse(String::from_utf8(text)?.as_str())?, None));
+    }
+
+    let userinfo = |cache: &SubscriptionCache| {
+        cache
+            .userinfo
+            .as_ref()
+            .and_then(|userinfo| userinfo.parse::<SubscriptionUserInfo>().ok())
+    };
+
+    let proxy = match &sub.fetch_proxy {
+        Some(FetchProxy::Url(proxy)) => Ok((Some(proxy.clone()), None)),
+        Some(FetchProxy::Outbound { target_file, tag }) => {
+            // Starting v2ray blocks until it is up or gives up.
+            let (ctl, target_file, tag) = (ctl.clone(), target_file.clone(), tag.clone());
+            tokio::task::spawn_blocking(move || {
+                start_outbound_proxy(&ctl, target_file.as_str(), tag.as_deref())
+                    .map(|(proxy, process)| (Some(proxy), Some(process)))
+                    .map_err(|err| err.to_string())
+            })
+            .await
+            .unwrap_or_else(|err| Err(err.to_string()))
+        }
+        None => Ok((None, None)),
+    };
+
+    let cache = SubscriptionCache::load(name, url);
+    let fetched = match proxy {
+        Ok((proxy, process)) => {
+            let fetched = download(sub, url, cache.as_ref(), proxy.as_deref())
+                .await
+                .and_then(|fresh| parse(fresh.payload.as_str()).map(|vlinks| (fresh, vlinks)));
+            if let Some(process) = process {
+                if let Err(err) = ctl.stop(process) {
+                    warn!("停止临时 V2ray 失败：{}", err);
+                }
+            }
+            fetched
+        }
+        Err(err) => Err(format!("启动临时 V2ray 失败：{}", err).into()),
+    };
+    match fetched {
+        Ok((fresh, vlinks)) => {
+            if let Err(err) = fresh.save(name) {
+                warn!("订阅『{}』缓存失败：{}", name, err);
+            }
+            Ok((vlinks, userinfo(&fresh)))
+        }
+        Err(err) => match cache {
+            Some(cache) => {
+                warn!(
+                    "订阅『{}』获取失败：{}，使用 {} 分钟前的缓存",
+                    name,
+                    err,
+                    cache.age_minutes()
+                );
+                Ok((parse(cache.payload.as_str())?, userinfo(&cache)))
+            }
+            None => Err(err),
+        },
+    }
+}
+
+/// Start a temporary v2ray running the outbound `tag` (or the first
+/// outbound) of `target_file`, returning the url of its local http proxy.
+fn start_outbound_proxy(
+    ctl: &V2rayApp,
+    target_file: &str,
+    tag: Option<&str>,
+) -> Result<(String, V2rayAppProcess), Box<dyn std::error::Error>> {
+    let v2ray_object = serde_json::from_slice::<V2rayObject>(std::fs::read(target_file)?.as_ref())?;
+    let outbound = v2ray_object
+        .outbounds
+        .iter()
+        .flatten()
+        .find(|o| tag.map(|tag| o.tag == tag).unwrap_or(true))
+        .ok_or_else(|| {
+            format!(
+                "outbound {} not found in {}",
+                tag.unwrap_or(""),
+                target_file
+            )
+        })?;
+
+    let mut v2ray_config = v2ray_template::gen_full_with_outbound(outbound);
+    let listen_port = pick_free_tcp_port();
+    let inbound = v2ray_config.inbounds.as_mut().unwrap().first_mut().unwrap();
+    inbound.port = Port::Int(listen_port);
+    let process = ctl.start(serde_json::to_string(&v2ray_config)?.as_str())?;
+    Ok((format!("http://127.0.0.1:{}", listen_port), process))
+}
+
+/// Download the subscription at `url`, through `proxy` if given. When the
+/// server reports that `cache` is still up to date, `cache` is returned
+/// with a refreshed fetch time and quota instead.
+async fn download(
+    sub: &Subscription,
+    url: &str,
+    cache: Option<&SubscriptionCache>,
+    proxy: Option<&str>,
+) -> Result<SubscriptionCache, Box<dyn std::error::Error>> {
+    use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+    let name = sub.name.as_str();
+    let mut client_builder =
+        reqwest::Client::builder().user_agent(sub.user_agent.as_deref().unwrap_or("V2rayMaid"));
+    if let Some(proxy) = proxy {
+        client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let mut request = client_builder.build()?.get(url);
+    for (key, value) in sub.headers.iter().flatten() {
+        request = request.header(key.as_str(), value.as_str());
+    }
+    if let Some(cache) = cache {
+        if let Some(etag) = &cache.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &cache.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+    }
+
+    let res = request.send().await?;
+    let userinfo = res
+        .headers()
+        .get("subscription-userinfo")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    if let (Some(cache), reqwest::StatusCode::NOT_MODIFIED) = (cache, res.status()) {
+        info!("订阅『{}』未更新，使用缓存", name);
+        let mut fresh = SubscriptionCache::new(url, cache.payload.clone());
+        fresh.etag = cache.etag.clone();
+        fresh.last_modified = cache.last_modified.clone();
+        fresh.userinfo = userinfo.or_else(|| cache.userinfo.clone());
+        return Ok(fresh);
+    }
+    let res = res.error_for_status()?;
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let etag = header(ETAG.as_str());
+    let last_modified = header(LAST_MODIFIED.as_str());
+    let payload = String::from_utf8(res.bytes().await?.to_vec())?;
+
+    let mut fresh = SubscriptionCache::new(url, payload);
+    fresh.etag = etag;
+    fresh.last_modified = last_modified;
+    fresh.userinfo = userinfo;
+    Ok(fresh)
+}
+
+/// Parse a subscription body, which is either a Clash configuration,
+/// a SIP008 JSON document or a (usually base64 encoded) list of share links.
+fn parse(text: &str) -> Result<Vec<VLink>, Box<dyn std::error::Error>> {
+    if clash::is_clash(text) {
+        return clash::parse(text);
+    }
+    if text.trim_start().starts_with('{') {
+        let sip008 = serde_json::from_str::<Sip008>(text)?;
+        if sip008.version != 1 {
+            return Err(format!("unsupported SIP008 version {}", sip008.version).into());
+        }
+        let mut v = Vec::new();
+        let mut errors = Vec::new();
+        for (i, server) in sip008.servers.into_iter().enumerate() {
+            match VLink::try_from(server) {
+                Ok(vlink) => v.push(vlink),
+                Err(reason) => errors.push(LinkError {
+                    line: i + 1,
+                    scheme: "sip008".to_string(),
+                    reason,
+                }),
+            }
+        }
+        report_link_errors(&errors);
+        return Ok(v);
+    }
+    match decode_base64(text) {
+        Ok(buf) => parse_share_links(String::from_utf8(buf)?.as_str()),
+        Err(_) if text.contains("://") => parse_share_links(text),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn parse_share_links(text: &str) -> Result<Vec<VLink>, Box<dyn std::error::Error>> {
     let mut v = Vec::new();
+    let mut errors = Vec::new();
 
-    let text = reqwest::Client::builder()
-        .user_agent("V2rayMaid")
-        .build()?
-        .get(url)
-        .send()
-        .await?
-        .bytes()
-        .await?;
-    let text = String::from_utf8(base64::decode(&text)?)?;
-
-    for line in text.lines() {
-        if !line.starts_with("vmess://") {
-            continue;
-        }
-        let buf = base64::decode(&line[8..])?;
-        let json = serde_json::from_slice::<VmessShare>(&buf);
-        if let Ok(vmess) = json {
-            v.push(vmess.into());
-        } else {
-            println!("{:?}", String::from_utf8(buf.clone()));
-            println!("反序列化失败");
-            continue;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let scheme = match line.split_once("://") {
+            Some((scheme, _)) => scheme,
+            None => continue,
+        };
+        let parsed = match scheme {
+            "vmess" => parse_vmess(line),
+            "ss" => parse_ss(line),
+            "trojan" | "vless" => parse_url_link(line),
+            _ => Err("unsupported scheme".into()),
+        };
+        match parsed {
+            Ok(vlink) => v.push(vlink),
+            Err(err) => errors.push(LinkError {
+                line: i + 1,
+                scheme: scheme.to_string(),
+                reason: err.to_string(),
+            }),
         }
     }
+
+    report_link_errors(&errors);
     Ok(v)
 }
+
+/// Encode `vlink` as a share link, the inverse of the parsers above.
+pub fn to_share_link(vlink: &VLink) -> Result<String, Box<dyn std::error::Error>> {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    let host = bracket(vlink.address.as_str());
+    let remarks = utf8_percent_encode(vlink.remarks.as_str(), NON_ALPHANUMERIC);
+
+    match &vlink.protocol {
+        Protocol::Vmess { .. } => {
+            let json = serde_json::to_string(&VmessShare::try_from(vlink)?)?;
+            Ok(format!("vmess://{}", base64::encode(json)))
+        }
+        Protocol::Shadowsocks {
+            method,
+            password,
+            plugin,
+        } => {
+            let user_info =
+                base64::encode_config(format!("{}:{}", method, password), base64::URL_SAFE_NO_PAD);
+            let mut link = format!("ss://{}@{}:{}", user_info, host, vlink.port);
+            if !plugin.is_empty() {
+                link.push_str("/?");
+                link.push_str(
+                    url::form_urlencoded::Serializer::new(String::new())
+                        .append_pair("plugin", plugin.as_str())
+                        .finish()
+                        .as_str(),
+                );
+            }
+            Ok(format!("{}#{}", link, remarks))
+        }
+        Protocol::Trojan { password } => Ok(url_share_link(vlink, password, &[])),
+        Protocol::Vless {
+            id,
+            encryption,
+            flow,
+        } => Ok(url_share_link(
+            vlink,
+            id,
+            &[("encryption", encryption), ("flow", flow)],
+        )),
+    }
+}
+
+/// Encode `vlink` as a Trojan/VLESS style URL share link, with the
+/// protocol specific non-empty query pairs of `extra`.
+fn url_share_link(vlink: &VLink, credential: &str, extra: &[(&str, &String)]) -> String {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+    let host = bracket(vlink.address.as_str());
+    let params = TransportParams::from(&vlink.transport);
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    query.append_pair("security", vlink.stream_security.as_str());
+    query.append_pair("type", params.network.as_str());
+    let (host_key, path_key, type_key) = match vlink.transport {
+        Transport::Quic { .. } => ("quicSecurity", "key", "headerType"),
+        Transport::Grpc { .. } => ("host", "serviceName", "mode"),
+        _ => ("host", "path", "headerType"),
+    };
+    let alpn = vlink.alpn.join(",");
+    for (key, value) in [
+        ("sni", &vlink.sni),
+        ("alpn", &alpn),
+        ("fp", &vlink.fingerprint),
+        (host_key, &params.host),
+        (path_key, &params.path),
+    ]
+    .iter()
+    .chain(extra)
+    {
+        if !value.is_empty() {
+            query.append_pair(key, value.as_str());
+        }
+    }
+    if !params.header_type.is_empty() && params.header_type != "none" {
+        query.append_pair(type_key, params.header_type.as_str());
+    }
+    if vlink.allow_insecure {
+        query.append_pair("allowInsecure", "1");
+    }
+    format!(
+        "{}://{}@{}:{}?{}#{}",
+        vlink.protocol.name(),
+        utf8_percent_encode(credential, NON_ALPHANUMERIC),
+        host,
+        vlink.port,
+        query.finish(),
+        utf8_percent_encode(vlink.remarks.as_str(), NON_ALPHANUMERIC)
+    )
+}
+
+/// Encode `vlinks` as a base64 subscription body, skipping nodes that
+/// cannot be expressed as a share link.
+pub fn encode(vlinks: &[VLink]) -> String {
+    let links: Vec<String> = vlinks
+        .iter()
+        .filter_map(|vlink| match to_share_link(vlink) {
+            Ok(link) => Some(link),
+            Err(err) => {
+                warn!("『{}』无法导出：{}", vlink.remarks, err);
+                None
+            }
+        })
+        .collect();
+    base64::encode(links.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_links_round_trip() {
+        let vlinks = vec![
+            VLink {
+                remarks: "vmess 节点".to_string(),
+                transport: Transport::Ws {
+                    host: "ws.example.com".to_string(),
+                    path: "/ws".to_string(),
+                },
+                stream_security: StreamSecurity::Tls,
+                sni: "sni.example.com".to_string(),
+                ..VLink::default()
+            },
+            VLink {
+                transport: Transport::Quic {
+                    security: "aes-128-gcm".to_string(),
+                    key: "secret".to_string(),
+                    header_type: "wechat-video".to_string(),
+                },
+                ..VLink::default()
+            },
+            VLink {
+                protocol: Protocol::Shadowsocks {
+                    method: "aes-256-gcm".to_string(),
+                    password: "pass/word".to_string(),
+                    plugin: "v2ray-plugin;mode=websocket;tls;host=ss.example.com;path=/ss"
+                        .to_string(),
+                },
+                address: "2001:db8::1".to_string(),
+                remarks: "ss".to_string(),
+                transport: Transport::Ws {
+                    host: "ss.example.com".to_string(),
+                    path: "/ss".to_string(),
+                },
+                stream_security: StreamSecurity::Tls,
+                ..VLink::default()
+            },
+            VLink {
+                protocol: Protocol::Trojan {
+                    password: "p@ss word".to_string(),
+                },
+                port: 443,
+                remarks: "trojan".to_string(),
+                transport: Transport::Ws {
+                    host: "trojan.example.com".to_string(),
+                    path: "/t?ed=2048".to_string(),
+                },
+                stream_security: StreamSecurity::Tls,
+                allow_insecure: true,
+                alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+                ..VLink::default()
+            },
+            VLink {
+                protocol: Protocol::Vless {
+                    id: "bf0067d4-831e-4911-b644-9b4582f69671".to_string(),
+                    encryption: "none".to_string(),
+                    flow: String::new(),
+                },
+                remarks: "vless".to_string(),
+                transport: Transport::Grpc {
+                    service_name: "grpc".to_string(),
+                    multi_mode: true,
+                },
+                stream_security: StreamSecurity::Tls,
+                fingerprint: "chrome".to_string(),
+                ..VLink::default()
+            },
+        ];
+
+        let links: Vec<String> = vlinks
+            .iter()
+            .map(|vlink| to_share_link(vlink).unwrap())
+            .collect();
+        let parsed = parse_share_links(links.join("\n").as_str()).unwrap();
+        assert_eq!(parsed.len(), vlinks.len());
+        for (parsed, vlink) in parsed.iter().zip(&vlinks) {
+            assert_eq!(parsed.protocol, vlink.protocol);
+            assert_eq!(parsed.address, vlink.address);
+            assert_eq!(parsed.port, vlink.port);
+            assert_eq!(parsed.remarks, vlink.remarks);
+            assert_eq!(parsed.transport, vlink.transport);
+            assert_eq!(parsed.stream_security, vlink.stream_security);
+            assert_eq!(parsed.sni, vlink.sni);
+            assert_eq!(parsed.allow_insecure, vlink.allow_insecure);
+            assert_eq!(parsed.alpn, vlink.alpn);
+            assert_eq!(parsed.fingerprint, vlink.fingerprint);
+        }
+    }
+}
@@ -29,3 +29,42 @@ pub fn pick_free_tcp_port() -> u16 {
     std::thread::sleep(std::time::Duration::from_millis(100));
     pick_free_tcp_port()
 }
+
+/// Decode base64 written with either the standard or the URL-safe alphabet,
+/// with or without trailing padding, as found in share links.
+pub fn decode_base64(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let s: String = s
+        .trim()
+        .trim_end_matches('=')
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    base64::decode_config(s, base64::STANDARD_NO_PAD)
+}
+
+/// Apply a JSON merge patch (RFC 7396) to `target`: objects are merged
+/// recursively, `null` removes a member and anything else replaces it.
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    use serde_json::Value;
+
+    if let Value::Object(patch) = patch {
+        if !target.is_object() {
+            *target = Value::Object(serde_json::Map::new());
+        }
+        let target = target.as_object_mut().unwrap();
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    } else {
+        *target = patch.clone();
+    }
+}
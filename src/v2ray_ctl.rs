@@ -3,8 +3,13 @@ use log::{error, info};
 use std::io::BufRead;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 use uuid::Uuid;
 
+/// How long v2ray may take to print its start banner.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct V2rayApp {
     program: PathBuf,
     version: String,
@@ -54,16 +59,38 @@ impl V2rayApp {
             .stdout(std::process::Stdio::piped())
             .args(&["-c", cfg_path.to_str().unwrap()])
             .spawn()?;
-        let mut reader = std::io::BufReader::new(child.stdout.as_mut().unwrap());
-        let mut s = String::new();
+        // Keep draining stdout for the lifetime of the process, so v2ray
+        // never blocks on a full pipe, and report when it has started.
+        let stdout = child.stdout.take().unwrap();
         let start_flag = format!("V2Ray {} started", self.version.as_str());
-        loop {
-            let num = reader.read_line(&mut s)?;
-            if num > 0 && s.rfind(start_flag.as_str()).is_some() {
-                break;
+        let (started, wait_started) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = std::io::BufReader::new(stdout);
+            let mut line = Vec::new();
+            while let Ok(num) = reader.read_until(b'\n', &mut line) {
+                if num == 0 {
+                    break;
+                }
+                if String::from_utf8_lossy(&line).contains(start_flag.as_str()) {
+                    let _ = started.send(());
+                }
+                line.clear();
+            }
+        });
+
+        match wait_started.recv_timeout(START_TIMEOUT) {
+            Ok(()) => Ok(V2rayAppProcess { child, cfg_path }),
+            Err(err) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = std::fs::remove_file(cfg_path);
+                Err(match err {
+                    RecvTimeoutError::Timeout => "v2ray did not start in time",
+                    RecvTimeoutError::Disconnected => "v2ray exited before starting",
+                }
+                .into())
             }
         }
-        Ok(V2rayAppProcess { child, cfg_path })
     }
 
     pub fn stop(&self, mut process: V2rayAppProcess) -> Result<(), Box<dyn std::error::Error>> {
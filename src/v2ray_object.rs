@@ -1,5 +1,6 @@
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
 
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct V2rayObject {
@@ -13,6 +14,26 @@ pub struct V2rayObject {
     pub outbounds: Option<Vec<outbound::OutboundObject>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub routing: Option<routing::RoutingObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<policy::PolicyObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reverse: Option<reverse::ReverseObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransportObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fakedns: Option<FakeDns>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observatory: Option<ObservatoryObject>,
+    /// Keys not modelled above, kept so a loaded config is written back
+    /// without losing them; every object below does the same. Modelled keys
+    /// are written in declaration order followed by these, so only their
+    /// order relative to each other, and that of map entries, is kept.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 pub mod dns {
@@ -23,24 +44,117 @@ pub mod dns {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub servers: Option<Vec<ServerObject>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub hosts: Option<HashMap<String, String>>,
+        pub hosts: Option<IndexMap<String, String>>,
         #[serde(rename = "clientIp", skip_serializing_if = "Option::is_none")]
         pub client_ip: Option<String>,
         #[serde(rename = "disableCache", skip_serializing_if = "Option::is_none")]
         pub disable_cache: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub tag: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct ServerObject {
         pub address: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub port: Option<super::Port>,
         #[serde(rename = "clientIp", skip_serializing_if = "Option::is_none")]
         pub client_ip: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub domains: Option<Vec<String>>,
         #[serde(rename = "exceptIps", skip_serializing_if = "Option::is_none")]
         pub except_ips: Option<Vec<String>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+}
+
+pub mod policy {
+    use super::*;
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct PolicyObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub levels: Option<IndexMap<String, LevelPolicyObject>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub system: Option<SystemPolicyObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct LevelPolicyObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub handshake: Option<i32>,
+        #[serde(rename = "connIdle", skip_serializing_if = "Option::is_none")]
+        pub conn_idle: Option<i32>,
+        #[serde(rename = "uplinkOnly", skip_serializing_if = "Option::is_none")]
+        pub uplink_only: Option<i32>,
+        #[serde(rename = "downlinkOnly", skip_serializing_if = "Option::is_none")]
+        pub downlink_only: Option<i32>,
+        #[serde(rename = "statsUserUplink", skip_serializing_if = "Option::is_none")]
+        pub stats_user_uplink: Option<bool>,
+        #[serde(rename = "statsUserDownlink", skip_serializing_if = "Option::is_none")]
+        pub stats_user_downlink: Option<bool>,
+        #[serde(rename = "bufferSize", skip_serializing_if = "Option::is_none")]
+        pub buffer_size: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct SystemPolicyObject {
+        #[serde(rename = "statsInboundUplink", skip_serializing_if = "Option::is_none")]
+        pub stats_inbound_uplink: Option<bool>,
+        #[serde(
+            rename = "statsInboundDownlink",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub stats_inbound_downlink: Option<bool>,
+        #[serde(
+            rename = "statsOutboundUplink",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub stats_outbound_uplink: Option<bool>,
+        #[serde(
+            rename = "statsOutboundDownlink",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub stats_outbound_downlink: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+}
+
+pub mod reverse {
+    use super::*;
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct ReverseObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub bridges: Option<Vec<BridgeObject>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub portals: Option<Vec<PortalObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct BridgeObject {
+        pub tag: String,
+        pub domain: String,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct PortalObject {
+        pub tag: String,
+        pub domain: String,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 }
 
@@ -70,6 +184,8 @@ pub mod routing {
         pub balancers: Option<Vec<BalancerObject>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub rules: Option<Vec<RuleObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -103,26 +219,31 @@ pub mod routing {
         pub attrs: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub protocol: Option<Vec<String>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct BalancerObject {
         pub tag: String,
         pub selector: Vec<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 }
 
 pub mod inbound {
     use super::*;
+    use std::convert::TryFrom;
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct InboundObject {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub listen: Option<String>,
         pub port: super::Port,
-        pub protocol: String,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub settings: Option<InboundConfigurationObject>,
+        /// `protocol` and the `settings` matching it.
+        #[serde(flatten)]
+        pub settings: InboundProtocolSettings,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub tag: Option<String>,
         #[serde(rename = "streamSettings", skip_serializing_if = "Option::is_none")]
@@ -131,48 +252,260 @@ pub mod inbound {
         pub sniffing: Option<SniffingObject>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub allocate: Option<AllocateObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    /// Inbound settings keyed on the protocol; protocols not modelled here
+    /// keep their settings as raw JSON.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(try_from = "super::ProtocolSettings", into = "super::ProtocolSettings")]
+    pub enum InboundProtocolSettings {
+        Http(Option<HttpInboundSettings>),
+        Socks(Option<SocksInboundSettings>),
+        DokodemoDoor(Option<DokodemoDoorInboundSettings>),
+        Vmess(Option<VmessInboundSettings>),
+        Vless(Option<VlessInboundSettings>),
+        Trojan(Option<TrojanInboundSettings>),
+        Shadowsocks(Option<ShadowsocksInboundSettings>),
+        Other {
+            protocol: String,
+            settings: Option<Value>,
+        },
+    }
+
+    impl InboundProtocolSettings {
+        pub fn protocol(&self) -> &str {
+            match self {
+                InboundProtocolSettings::Http(_) => "http",
+                InboundProtocolSettings::Socks(_) => "socks",
+                InboundProtocolSettings::DokodemoDoor(_) => "dokodemo-door",
+                InboundProtocolSettings::Vmess(_) => "vmess",
+                InboundProtocolSettings::Vless(_) => "vless",
+                InboundProtocolSettings::Trojan(_) => "trojan",
+                InboundProtocolSettings::Shadowsocks(_) => "shadowsocks",
+                InboundProtocolSettings::Other { protocol, .. } => protocol.as_str(),
+            }
+        }
+    }
+
+    impl TryFrom<super::ProtocolSettings> for InboundProtocolSettings {
+        type Error = serde_json::Error;
+
+        fn try_from(raw: super::ProtocolSettings) -> Result<Self, Self::Error> {
+            use super::ProtocolSettings as Raw;
+            Ok(match raw.protocol.as_str() {
+                "http" => InboundProtocolSettings::Http(Raw::parse(raw.settings)?),
+                "socks" => InboundProtocolSettings::Socks(Raw::parse(raw.settings)?),
+                "dokodemo-door" => InboundProtocolSettings::DokodemoDoor(Raw::parse(raw.settings)?),
+                "vmess" => InboundProtocolSettings::Vmess(Raw::parse(raw.settings)?),
+                "vless" => InboundProtocolSettings::Vless(Raw::parse(raw.settings)?),
+                "trojan" => InboundProtocolSettings::Trojan(Raw::parse(raw.settings)?),
+                "shadowsocks" => InboundProtocolSettings::Shadowsocks(Raw::parse(raw.settings)?),
+                _ => InboundProtocolSettings::Other {
+                    protocol: raw.protocol,
+                    settings: raw.settings,
+                },
+            })
+        }
+    }
+
+    impl From<InboundProtocolSettings> for super::ProtocolSettings {
+        fn from(settings: InboundProtocolSettings) -> Self {
+            use super::ProtocolSettings as Raw;
+            let protocol = settings.protocol().to_string();
+            let settings = match settings {
+                InboundProtocolSettings::Http(s) => Raw::value(s),
+                InboundProtocolSettings::Socks(s) => Raw::value(s),
+                InboundProtocolSettings::DokodemoDoor(s) => Raw::value(s),
+                InboundProtocolSettings::Vmess(s) => Raw::value(s),
+                InboundProtocolSettings::Vless(s) => Raw::value(s),
+                InboundProtocolSettings::Trojan(s) => Raw::value(s),
+                InboundProtocolSettings::Shadowsocks(s) => Raw::value(s),
+                InboundProtocolSettings::Other { settings, .. } => settings,
+            };
+            Self { protocol, settings }
+        }
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
-    pub struct InboundConfigurationObject {
+    pub struct HttpInboundSettings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub timeout: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub accounts: Option<Vec<AccountObject>>,
+        #[serde(rename = "allowTransparent", skip_serializing_if = "Option::is_none")]
+        pub allow_transparent: Option<bool>,
+        #[serde(rename = "userLevel", skip_serializing_if = "Option::is_none")]
+        pub user_level: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct SocksInboundSettings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub auth: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub accounts: Option<Vec<AccountObject>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub udp: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub ip: Option<String>,
         #[serde(rename = "userLevel", skip_serializing_if = "Option::is_none")]
         pub user_level: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct DokodemoDoorInboundSettings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub address: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub port: Option<u16>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub network: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub timeout: Option<i32>,
+        #[serde(rename = "followRedirect", skip_serializing_if = "Option::is_none")]
+        pub follow_redirect: Option<bool>,
+        #[serde(rename = "userLevel", skip_serializing_if = "Option::is_none")]
+        pub user_level: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct VmessInboundSettings {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub clients: Option<Vec<VmessClientObject>>,
+        #[serde(
+            rename = "disableInsecureEncryption",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub disable_insecure_encryption: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct VmessClientObject {
+        pub id: String,
+        #[serde(rename = "alterId", skip_serializing_if = "Option::is_none")]
+        pub alter_id: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub level: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub email: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct VlessInboundSettings {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub clients: Option<Vec<VlessClientObject>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub decryption: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub fallbacks: Option<Vec<FallbackObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct VlessClientObject {
+        pub id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub flow: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub level: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub email: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct TrojanInboundSettings {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub clients: Option<Vec<TrojanClientObject>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub fallbacks: Option<Vec<FallbackObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct TrojanClientObject {
+        pub password: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub level: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub email: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct FallbackObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub alpn: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub path: Option<String>,
+        pub dest: super::Port,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub xver: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct ShadowsocksInboundSettings {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub email: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub method: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub password: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub level: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub network: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
     pub struct AccountObject {
         pub user: String,
         pub pass: String,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct SniffingObject {
         pub enabled: bool,
-        #[serde(rename = "destOverride")]
+        #[serde(rename = "destOverride", skip_serializing_if = "Option::is_none")]
         pub dest_override: Option<Vec<String>>,
-        #[serde(rename = "metadata_only")]
-        pub metadata_only: bool,
+        #[serde(rename = "metadataOnly", skip_serializing_if = "Option::is_none")]
+        pub metadata_only: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct AllocateObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub strategy: Option<Strategy>,
-        pub refresh: i32,
-        pub concurrency: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub refresh: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub concurrency: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -185,39 +518,157 @@ pub mod inbound {
 
 pub mod outbound {
     use super::*;
+    use std::convert::TryFrom;
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct OutboundObject {
         #[serde(rename = "sendThrough", skip_serializing_if = "Option::is_none")]
         pub send_through: Option<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
         pub tag: String,
-        pub protocol: String,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub settings: Option<OutboundConfigurationObject>,
+        /// `protocol` and the `settings` matching it.
+        #[serde(flatten)]
+        pub settings: OutboundProtocolSettings,
         #[serde(rename = "streamSettings", skip_serializing_if = "Option::is_none")]
         pub stream_settings: Option<super::stream_settings::StreamSettingsObject>,
+        #[serde(rename = "proxySettings", skip_serializing_if = "Option::is_none")]
+        pub proxy_settings: Option<ProxySettings>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub mux: Option<MuxObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub enum DomainStrategy {
-        AsIs,
-        UseIP,
-        UseIPv4,
-        UseIPv6,
+    /// Outbound settings keyed on the protocol; protocols not modelled here
+    /// keep their settings as raw JSON.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(try_from = "super::ProtocolSettings", into = "super::ProtocolSettings")]
+    pub enum OutboundProtocolSettings {
+        Vmess(Option<VnextSettings>),
+        Vless(Option<VnextSettings>),
+        Trojan(Option<ServersSettings<super::TrojanServerObject>>),
+        Shadowsocks(Option<ServersSettings<super::ShadowsocksServerObject>>),
+        Socks(Option<ServersSettings<super::SocksServerObject>>),
+        Http(Option<ServersSettings<super::SocksServerObject>>),
+        Freedom(Option<FreedomSettings>),
+        Blackhole(Option<BlackholeSettings>),
+        Dns(Option<DnsSettings>),
+        Other {
+            protocol: String,
+            settings: Option<Value>,
+        },
+    }
+
+    impl OutboundProtocolSettings {
+        pub fn protocol(&self) -> &str {
+            match self {
+                OutboundProtocolSettings::Vmess(_) => "vmess",
+                OutboundProtocolSettings::Vless(_) => "vless",
+                OutboundProtocolSettings::Trojan(_) => "trojan",
+                OutboundProtocolSettings::Shadowsocks(_) => "shadowsocks",
+                OutboundProtocolSettings::Socks(_) => "socks",
+                OutboundProtocolSettings::Http(_) => "http",
+                OutboundProtocolSettings::Freedom(_) => "freedom",
+                OutboundProtocolSettings::Blackhole(_) => "blackhole",
+                OutboundProtocolSettings::Dns(_) => "dns",
+                OutboundProtocolSettings::Other { protocol, .. } => protocol.as_str(),
+            }
+        }
     }
 
+    impl TryFrom<super::ProtocolSettings> for OutboundProtocolSettings {
+        type Error = serde_json::Error;
+
+        fn try_from(raw: super::ProtocolSettings) -> Result<Self, Self::Error> {
+            use super::ProtocolSettings as Raw;
+            Ok(match raw.protocol.as_str() {
+                "vmess" => OutboundProtocolSettings::Vmess(Raw::parse(raw.settings)?),
+                "vless" => OutboundProtocolSettings::Vless(Raw::parse(raw.settings)?),
+                "trojan" => OutboundProtocolSettings::Trojan(Raw::parse(raw.settings)?),
+                "shadowsocks" => OutboundProtocolSettings::Shadowsocks(Raw::parse(raw.settings)?),
+                "socks" => OutboundProtocolSettings::Socks(Raw::parse(raw.settings)?),
+                "http" => OutboundProtocolSettings::Http(Raw::parse(raw.settings)?),
+                "freedom" => OutboundProtocolSettings::Freedom(Raw::parse(raw.settings)?),
+                "blackhole" => OutboundProtocolSettings::Blackhole(Raw::parse(raw.settings)?),
+                "dns" => OutboundProtocolSettings::Dns(Raw::parse(raw.settings)?),
+                _ => OutboundProtocolSettings::Other {
+                    protocol: raw.protocol,
+                    settings: raw.settings,
+                },
+            })
+        }
+    }
+
+    impl From<OutboundProtocolSettings> for super::ProtocolSettings {
+        fn from(settings: OutboundProtocolSettings) -> Self {
+            use super::ProtocolSettings as Raw;
+            let protocol = settings.protocol().to_string();
+            let settings = match settings {
+                OutboundProtocolSettings::Vmess(s) | OutboundProtocolSettings::Vless(s) => {
+                    Raw::value(s)
+                }
+                OutboundProtocolSettings::Trojan(s) => Raw::value(s),
+                OutboundProtocolSettings::Shadowsocks(s) => Raw::value(s),
+                OutboundProtocolSettings::Socks(s) | OutboundProtocolSettings::Http(s) => {
+                    Raw::value(s)
+                }
+                OutboundProtocolSettings::Freedom(s) => Raw::value(s),
+                OutboundProtocolSettings::Blackhole(s) => Raw::value(s),
+                OutboundProtocolSettings::Dns(s) => Raw::value(s),
+                OutboundProtocolSettings::Other { settings, .. } => settings,
+            };
+            Self { protocol, settings }
+        }
+    }
+
+    /// Settings of VMess and VLESS outbounds.
     #[derive(Debug, Clone, Deserialize, Serialize)]
-    pub struct OutboundConfigurationObject {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub vnext: Option<Vec<super::ServerObject>>,
+    pub struct VnextSettings {
+        pub vnext: Vec<super::ServerObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    /// Settings of outbounds listing their `servers`.
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    pub struct ServersSettings<T> {
+        pub servers: Vec<T>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct FreedomSettings {
+        /// e.g. `AsIs` or `UseIPv4`; kept as a string since forks add their own.
+        #[serde(rename = "domainStrategy", skip_serializing_if = "Option::is_none")]
+        pub domain_strategy: Option<String>,
+        /// `host:port` every connection is sent to instead of its destination.
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub servers: Option<Vec<super::SocksServerObject>>,
+        pub redirect: Option<String>,
+        #[serde(rename = "userLevel", skip_serializing_if = "Option::is_none")]
+        pub user_level: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct BlackholeSettings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub response: Option<super::Response>,
-        #[serde(rename = "domainStrategy", skip_serializing_if = "Option::is_none")]
-        pub domain_strategy: Option<DomainStrategy>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct DnsSettings {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub network: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub address: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub port: Option<u16>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -226,6 +677,8 @@ pub mod outbound {
         pub tag: Option<String>,
         #[serde(rename = "transportLayer", skip_serializing_if = "Option::is_none")]
         pub transport_layer: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -233,6 +686,8 @@ pub mod outbound {
         pub enabled: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub concurrency: Option<i32>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 }
 
@@ -241,16 +696,51 @@ pub struct ServerObject {
     pub address: String,
     pub port: Port,
     pub users: Vec<UserObject>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UserObject {
     pub id: String,
-    #[serde(rename = "alterId")]
-    pub alter_id: i32,
+    #[serde(rename = "alterId", skip_serializing_if = "Option::is_none")]
+    pub alter_id: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub security: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<i32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ShadowsocksServerObject {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub address: String,
+    pub port: u16,
+    pub method: String,
+    pub password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<i32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TrojanServerObject {
+    pub address: String,
+    pub port: u16,
+    pub password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<i32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -259,18 +749,27 @@ pub struct SocksServerObject {
     pub port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub users: Option<Vec<SocksUserObject>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SocksUserObject {
     pub user: String,
     pub pass: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<i32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/// What a blackhole outbound answers before closing the connection.
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Response {
-    pub r#type: String,
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Response {
+    None,
+    /// An HTTP 403 response.
+    Http,
 }
 
 pub mod stream_settings {
@@ -278,6 +777,7 @@ pub mod stream_settings {
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
     pub struct StreamSettingsObject {
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub network: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub security: Option<String>,
@@ -295,8 +795,12 @@ pub mod stream_settings {
         pub quic_settings: Option<QuicObject>,
         #[serde(rename = "dsSettings", skip_serializing_if = "Option::is_none")]
         pub ds_settings: Option<DomainSocketObject>,
+        #[serde(rename = "grpcSettings", skip_serializing_if = "Option::is_none")]
+        pub grpc_settings: Option<GrpcObject>,
         #[serde(rename = "sockopt", skip_serializing_if = "Option::is_none")]
         pub sock_opt: Option<SockOpt>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -311,6 +815,10 @@ pub mod stream_settings {
         pub certificates: Option<Vec<CertificateObject>>,
         #[serde(rename = "disableSystemRoot", skip_serializing_if = "Option::is_none")]
         pub disable_system_root: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub fingerprint: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -325,23 +833,37 @@ pub mod stream_settings {
         pub certificate: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub key: Option<Vec<String>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct TcpObject {
-        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(
+            rename = "acceptProxyProtocol",
+            skip_serializing_if = "Option::is_none"
+        )]
         pub accept_proxy_protocol: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub header: Option<PseudoHeaderObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Debug, Clone, Deserialize, Serialize)]
     #[serde(tag = "type", rename_all = "camelCase")]
     pub enum PseudoHeaderObject {
-        None,
+        None {
+            #[serde(flatten)]
+            extra: Map<String, Value>,
+        },
         Http {
+            #[serde(skip_serializing_if = "Option::is_none")]
             request: Option<HttpRequestObject>,
+            #[serde(skip_serializing_if = "Option::is_none")]
             response: Option<HttpResponseObject>,
+            #[serde(flatten)]
+            extra: Map<String, Value>,
         },
     }
 
@@ -354,7 +876,9 @@ pub mod stream_settings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub path: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub headers: Option<HashMap<String, HttpHeaderValueObject>>,
+        pub headers: Option<IndexMap<String, HttpHeaderValueObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(untagged)]
@@ -372,7 +896,9 @@ pub mod stream_settings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub reason: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub headers: Option<HashMap<String, HttpHeaderValueObject>>,
+        pub headers: Option<IndexMap<String, HttpHeaderValueObject>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -395,12 +921,16 @@ pub mod stream_settings {
         pub header: Option<HeaderObject>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub seed: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
     pub struct HeaderObject {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub r#type: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -413,7 +943,9 @@ pub mod stream_settings {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub path: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub headers: Option<HashMap<String, String>>,
+        pub headers: Option<IndexMap<String, String>>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -422,6 +954,8 @@ pub mod stream_settings {
         pub host: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub path: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -432,6 +966,18 @@ pub mod stream_settings {
         pub key: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub header: Option<HeaderObject>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
+    }
+
+    #[derive(Default, Debug, Clone, Deserialize, Serialize)]
+    pub struct GrpcObject {
+        #[serde(rename = "serviceName", skip_serializing_if = "Option::is_none")]
+        pub service_name: Option<String>,
+        #[serde(rename = "multiMode", skip_serializing_if = "Option::is_none")]
+        pub multi_mode: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -442,6 +988,8 @@ pub mod stream_settings {
         pub r#abstract: Option<bool>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub padding: Option<bool>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 
     #[derive(Default, Debug, Clone, Deserialize, Serialize)]
@@ -450,11 +998,104 @@ pub mod stream_settings {
         pub mark: Option<i32>,
         #[serde(rename = "tcpFastOpen", skip_serializing_if = "Option::is_none")]
         pub tcp_fast_open: Option<bool>,
+        /// `redirect`, `tproxy` or `off`.
         #[serde(skip_serializing_if = "Option::is_none")]
-        pub tproxy: Option<bool>,
+        pub tproxy: Option<String>,
+        #[serde(rename = "domainStrategy", skip_serializing_if = "Option::is_none")]
+        pub domain_strategy: Option<String>,
+        #[serde(flatten)]
+        pub extra: Map<String, Value>,
     }
 }
 
+/// The `protocol` and untyped `settings` of an inbound or outbound, through
+/// which their typed settings are (de)serialized.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProtocolSettings {
+    pub protocol: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+}
+
+impl ProtocolSettings {
+    fn parse<T: serde::de::DeserializeOwned>(
+        settings: Option<Value>,
+    ) -> Result<Option<T>, serde_json::Error> {
+        settings.map(serde_json::from_value).transpose()
+    }
+
+    fn value<T: Serialize>(settings: Option<T>) -> Option<Value> {
+        settings.map(|s| serde_json::to_value(s).expect("settings serialize to JSON"))
+    }
+}
+
+/// Enables traffic statistics; it has no options of its own.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct StatsObject {
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiObject {
+    pub tag: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub services: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Default transport settings for every inbound and outbound.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct TransportObject {
+    #[serde(rename = "tcpSettings", skip_serializing_if = "Option::is_none")]
+    pub tcp_settings: Option<stream_settings::TcpObject>,
+    #[serde(rename = "kcpSettings", skip_serializing_if = "Option::is_none")]
+    pub kcp_settings: Option<stream_settings::KcpObject>,
+    #[serde(rename = "wsSettings", skip_serializing_if = "Option::is_none")]
+    pub ws_settings: Option<stream_settings::WebSocketObject>,
+    #[serde(rename = "httpSettings", skip_serializing_if = "Option::is_none")]
+    pub http_settings: Option<stream_settings::HttpObject>,
+    #[serde(rename = "quicSettings", skip_serializing_if = "Option::is_none")]
+    pub quic_settings: Option<stream_settings::QuicObject>,
+    #[serde(rename = "dsSettings", skip_serializing_if = "Option::is_none")]
+    pub ds_settings: Option<stream_settings::DomainSocketObject>,
+    #[serde(rename = "grpcSettings", skip_serializing_if = "Option::is_none")]
+    pub grpc_settings: Option<stream_settings::GrpcObject>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A single fake DNS pool, or a list of pools.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FakeDns {
+    Pool(FakeDnsObject),
+    Pools(Vec<FakeDnsObject>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FakeDnsObject {
+    #[serde(rename = "ipPool")]
+    pub ip_pool: String,
+    #[serde(rename = "poolSize", skip_serializing_if = "Option::is_none")]
+    pub pool_size: Option<u32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct ObservatoryObject {
+    #[serde(rename = "subjectSelector", skip_serializing_if = "Option::is_none")]
+    pub subject_selector: Option<Vec<String>>,
+    #[serde(rename = "probeURL", skip_serializing_if = "Option::is_none")]
+    pub probe_url: Option<String>,
+    #[serde(rename = "probeInterval", skip_serializing_if = "Option::is_none")]
+    pub probe_interval: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LogObject {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -463,6 +1104,8 @@ pub struct LogObject {
     pub error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub loglevel: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -471,3 +1114,97 @@ pub enum Port {
     Int(u16),
     String(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_unknown_keys_and_map_order() {
+        // Written the way configs usually are, not in declaration order.
+        let json = r#"{
+            "outbounds": [{
+                "protocol": "vmess",
+                "unknownOutboundKey": [1, 2],
+                "tag": "proxy",
+                "settings": {"vnext": [{"address": "v2ray.cool", "port": 10086, "users": [{"id": "a3482e88-686a-4a58-8126-99c9df64b7bf", "level": 8}]}]},
+                "streamSettings": {
+                    "unknownStreamKey": "x",
+                    "network": "tcp",
+                    "tcpSettings": {
+                        "header": {
+                            "type": "http",
+                            "unknownHeaderKey": 1,
+                            "request": {"path": ["/"], "headers": {"Host": ["b.example.com"], "Accept": "*/*"}}
+                        }
+                    },
+                    "wsSettings": {"path": "/ws", "headers": {"X-B": "1", "X-A": "2"}}
+                }
+            }, {
+                "protocol": "freedom",
+                "tag": "direct",
+                "streamSettings": {"tcpSettings": {"header": {"type": "none", "unknownHeaderKey": 2}}}
+            }],
+            "unknownTopLevelKey": {"b": 1, "a": 2},
+            "inbounds": [{
+                "port": 1080,
+                "listen": "127.0.0.1",
+                "protocol": "socks",
+                "settings": {"udp": true, "someFlag": 1}
+            }],
+            "dns": {
+                "hosts": {"z.example.com": "10.0.0.1", "a.example.com": "10.0.0.2", "m.example.com": "10.0.0.3"},
+                "queryStrategy": "UseIPv4",
+                "servers": [{"address": "1.1.1.1", "port": 53, "skipFallback": true}]
+            },
+            "policy": {"levels": {"8": {"connIdle": 300}, "0": {"bufferSize": 512, "handshake": 4}}},
+            "log": {"loglevel": "warning", "dnsLog": true},
+            "anotherUnknownKey": true
+        }"#;
+        // Modelled keys come back in declaration order followed by the
+        // unknown ones, which, like map entries, keep their own order.
+        let expected = r#"{
+            "log": {"loglevel": "warning", "dnsLog": true},
+            "dns": {
+                "servers": [{"address": "1.1.1.1", "port": 53, "skipFallback": true}],
+                "hosts": {"z.example.com": "10.0.0.1", "a.example.com": "10.0.0.2", "m.example.com": "10.0.0.3"},
+                "queryStrategy": "UseIPv4"
+            },
+            "inbounds": [{
+                "listen": "127.0.0.1",
+                "port": 1080,
+                "protocol": "socks",
+                "settings": {"udp": true, "someFlag": 1}
+            }],
+            "outbounds": [{
+                "tag": "proxy",
+                "protocol": "vmess",
+                "settings": {"vnext": [{"address": "v2ray.cool", "port": 10086, "users": [{"id": "a3482e88-686a-4a58-8126-99c9df64b7bf", "level": 8}]}]},
+                "streamSettings": {
+                    "network": "tcp",
+                    "tcpSettings": {
+                        "header": {
+                            "type": "http",
+                            "request": {"path": ["/"], "headers": {"Host": ["b.example.com"], "Accept": "*/*"}},
+                            "unknownHeaderKey": 1
+                        }
+                    },
+                    "wsSettings": {"path": "/ws", "headers": {"X-B": "1", "X-A": "2"}},
+                    "unknownStreamKey": "x"
+                },
+                "unknownOutboundKey": [1, 2]
+            }, {
+                "tag": "direct",
+                "protocol": "freedom",
+                "streamSettings": {"tcpSettings": {"header": {"type": "none", "unknownHeaderKey": 2}}}
+            }],
+            "policy": {"levels": {"8": {"connIdle": 300}, "0": {"handshake": 4, "bufferSize": 512}}},
+            "unknownTopLevelKey": {"b": 1, "a": 2},
+            "anotherUnknownKey": true
+        }"#;
+        let expected =
+            serde_json::to_string(&serde_json::from_str::<Value>(expected).unwrap()).unwrap();
+        let v = serde_json::from_str::<V2rayObject>(json).unwrap();
+        assert_eq!(serde_json::to_string(&v).unwrap(), expected);
+    }
+}
@@ -1,6 +1,14 @@
-use crate::v2ray_object::V2rayObject;
-use crate::vlink::VLink;
-use std::collections::HashMap;
+use crate::settings::OutboundSettings;
+use crate::utils::merge_patch;
+use crate::v2ray_object::outbound::{
+    MuxObject, OutboundObject, OutboundProtocolSettings, ServersSettings, VnextSettings,
+};
+use crate::v2ray_object::{
+    Port, ServerObject, ShadowsocksServerObject, TrojanServerObject, UserObject, V2rayObject,
+};
+use crate::vlink::{Protocol, StreamSecurity, Transport, VLink};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 
 const V2RAY_TPL: &str = r#"
 {
@@ -25,15 +33,6 @@ const V2RAY_TPL: &str = r#"
                         "security": "auto",
                         "level": 8
                     }]
-                }],
-                "servers": [{
-                    "address": "127.0.0.1",
-                    "port": 1234,
-                    "users": [{
-                        "user": "test user",
-                        "pass": "test pass",
-                        "level": 0
-                    }]
                 }]
             },
             "streamSettings": {
@@ -61,10 +60,112 @@ const V2RAY_TPL: &str = r#"
 }
 "#;
 
+/// JSON merge patches (RFC 7396) layered over the generated configs, e.g.
+/// `{"outbound": {"sendThrough": "10.0.0.2", "streamSettings": {"sockopt": {"mark": 255}}}}`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Template {
+    /// Patch applied to every generated outbound; the node fields and the
+    /// outbound settings that are set take precedence over it. `mux` falls
+    /// back to disabled with a concurrency of 8 when neither sets it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<serde_json::Value>,
+    /// Patch applied to the whole config used to test a node.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full: Option<serde_json::Value>,
+}
+
+impl Template {
+    /// Load the template at `file`, checking its patches yield valid configs
+    /// for every protocol and keep the http inbound the nodes are tested through.
+    pub fn load(file: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let template = serde_json::from_slice::<Template>(std::fs::read(file)?.as_ref())?;
+        let global_settings = GlobalSettings {
+            template: Some(template.clone()),
+            ..GlobalSettings::default()
+        };
+        let protocols = vec![
+            VLink::default().protocol,
+            Protocol::Vless {
+                id: "bf0067d4-831e-4911-b644-9b4582f69671".to_string(),
+                encryption: "none".to_string(),
+                flow: "".to_string(),
+            },
+            Protocol::Trojan {
+                password: "password".to_string(),
+            },
+            Protocol::Shadowsocks {
+                method: "aes-128-gcm".to_string(),
+                password: "password".to_string(),
+                plugin: "".to_string(),
+            },
+        ];
+        for protocol in protocols {
+            let name = protocol.name();
+            let vlink = VLink {
+                protocol,
+                ..VLink::default()
+            };
+            let v = vlink
+                .gen_full(Some(&global_settings))
+                .map_err(|err| format!("{}: {}", name, err))?;
+            match v.inbounds.as_ref().and_then(|inbounds| inbounds.first()) {
+                Some(inbound) if inbound.settings.protocol() == "http" => {}
+                _ => return Err("full: the first inbound must stay an http inbound".into()),
+            }
+        }
+        Ok(template)
+    }
+
+    /// Apply `patch` to `object`, failing when the result no longer
+    /// deserializes as `T`.
+    fn patch<T>(patch: &serde_json::Value, object: &mut T) -> Result<(), Box<dyn std::error::Error>>
+    where
+        T: Serialize + serde::de::DeserializeOwned,
+    {
+        let mut v = serde_json::to_value(&*object)?;
+        merge_patch(&mut v, patch);
+        *object = serde_json::from_value(v)?;
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct GlobalSettings {
     pub use_mux: Option<bool>,
     pub mux_concurrency: Option<i32>,
+    pub domain_strategy: Option<String>,
+    pub mark: Option<i32>,
+    pub tcp_fast_open: Option<bool>,
+    pub level: Option<i32>,
+    pub template: Option<Template>,
+}
+
+impl GlobalSettings {
+    /// These settings with the options set in `outbound` overriding them.
+    pub fn with(&self, outbound: Option<&OutboundSettings>) -> Self {
+        let mut settings = self.clone();
+        if let Some(outbound) = outbound {
+            if outbound.mux.is_some() {
+                settings.use_mux = outbound.mux;
+            }
+            if outbound.mux_concurrency.is_some() {
+                settings.mux_concurrency = outbound.mux_concurrency;
+            }
+            if outbound.domain_strategy.is_some() {
+                settings.domain_strategy = outbound.domain_strategy.clone();
+            }
+            if outbound.mark.is_some() {
+                settings.mark = outbound.mark;
+            }
+            if outbound.tcp_fast_open.is_some() {
+                settings.tcp_fast_open = outbound.tcp_fast_open;
+            }
+            if outbound.level.is_some() {
+                settings.level = outbound.level;
+            }
+        }
+        settings
+    }
 }
 
 impl Default for GlobalSettings {
@@ -74,76 +175,192 @@ impl Default for GlobalSettings {
 }
 
 const GLOBAL_SETTINGS: GlobalSettings = GlobalSettings {
-    use_mux: Some(false),
-    mux_concurrency: Some(8),
+    use_mux: None,
+    mux_concurrency: None,
+    domain_strategy: None,
+    mark: None,
+    tcp_fast_open: None,
+    level: Some(8),
+    template: None,
 };
 
+/// A full config like `VLink::gen_full`, routing through `outbound` as is.
+pub fn gen_full_with_outbound(outbound: &OutboundObject) -> V2rayObject {
+    let mut v = serde_json::from_str::<V2rayObject>(V2RAY_TPL).unwrap();
+    let mut outbound = outbound.clone();
+    outbound.tag = "proxy".to_string();
+    v.outbounds.as_mut().unwrap()[0] = outbound;
+    v
+}
+
 impl VLink {
-    pub fn gen_full(&self, global_settings: Option<&GlobalSettings>) -> V2rayObject {
+    pub fn gen_full(
+        &self,
+        global_settings: Option<&GlobalSettings>,
+    ) -> Result<V2rayObject, Box<dyn std::error::Error>> {
         let global_settings = global_settings.unwrap_or(&GLOBAL_SETTINGS);
         let mut v = serde_json::from_str::<V2rayObject>(V2RAY_TPL).unwrap();
         let outbound = v.outbounds.as_mut().unwrap().get_mut(0).unwrap();
-        self.fill_outbound(outbound, "proxy", global_settings);
-        v
+        self.fill_outbound(outbound, "proxy", global_settings)
+            .map_err(|err| format!("outbound: {}", err))?;
+        if let Some(patch) = global_settings
+            .template
+            .as_ref()
+            .and_then(|t| t.full.as_ref())
+        {
+            Template::patch(patch, &mut v).map_err(|err| format!("full: {}", err))?;
+        }
+        Ok(v)
     }
     pub fn gen_outbound(
         &self,
         tag: &str,
         global_settings: Option<&GlobalSettings>,
-    ) -> crate::v2ray_object::outbound::OutboundObject {
+    ) -> Result<crate::v2ray_object::outbound::OutboundObject, Box<dyn std::error::Error>> {
         let global_settings = global_settings.unwrap_or(&GLOBAL_SETTINGS);
         let mut outbound = serde_json::from_str::<V2rayObject>(V2RAY_TPL)
             .unwrap()
             .outbounds
             .unwrap()[0]
             .clone();
-        self.fill_outbound(&mut outbound, tag, global_settings);
-        outbound
+        self.fill_outbound(&mut outbound, tag, global_settings)?;
+        Ok(outbound)
     }
     fn fill_outbound(
         &self,
         outbound: &mut crate::v2ray_object::outbound::OutboundObject,
         tag: &str,
         global_settings: &GlobalSettings,
-    ) {
+    ) -> Result<(), Box<dyn std::error::Error>> {
         outbound.tag = tag.to_string();
-        let mut mux = crate::v2ray_object::outbound::MuxObject::default();
-        {
-            if let Some(enabled) = global_settings.use_mux {
-                mux.enabled = enabled;
+
+        outbound.settings = match &self.protocol {
+            Protocol::Vmess {
+                id,
+                alter_id,
+                security,
+            } => OutboundProtocolSettings::Vmess(Some(self.gen_vnext_settings(UserObject {
+                id: id.clone(),
+                alter_id: Some(*alter_id),
+                security: Some(security.clone()),
+                encryption: None,
+                flow: None,
+                level: global_settings.level,
+                extra: Default::default(),
+            }))),
+            Protocol::Vless {
+                id,
+                encryption,
+                flow,
+            } => OutboundProtocolSettings::Vless(Some(self.gen_vnext_settings(UserObject {
+                id: id.clone(),
+                alter_id: None,
+                security: None,
+                encryption: Some(if encryption.is_empty() {
+                    "none".to_string()
+                } else {
+                    encryption.clone()
+                }),
+                flow: if flow.is_empty() {
+                    None
+                } else {
+                    Some(flow.clone())
+                },
+                level: global_settings.level,
+                extra: Default::default(),
+            }))),
+            Protocol::Shadowsocks {
+                method, password, ..
+            } => OutboundProtocolSettings::Shadowsocks(Some(ServersSettings {
+                servers: vec![ShadowsocksServerObject {
+                    email: None,
+                    address: self.address.clone(),
+                    port: self.port,
+                    method: method.clone(),
+                    password: password.clone(),
+                    level: global_settings.level,
+                    extra: Default::default(),
+                }],
+                extra: Default::default(),
+            })),
+            Protocol::Trojan { password } => {
+                OutboundProtocolSettings::Trojan(Some(ServersSettings {
+                    servers: vec![TrojanServerObject {
+                        address: self.address.clone(),
+                        port: self.port,
+                        password: password.clone(),
+                        email: None,
+                        level: global_settings.level,
+                        extra: Default::default(),
+                    }],
+                    extra: Default::default(),
+                }))
             }
+        };
+        // Filled in last, so the template can supply it.
+        outbound.mux = None;
+        outbound.stream_settings = match self.protocol {
+            Protocol::Shadowsocks { .. } if self.transport == Transport::tcp() => None,
+            _ => Some(self.gen_bound_stream_settings()),
+        };
 
-            if let Some(concurrency) = global_settings.mux_concurrency {
-                mux.concurrency = Some(concurrency);
+        if global_settings.domain_strategy.is_some()
+            || global_settings.mark.is_some()
+            || global_settings.tcp_fast_open.is_some()
+        {
+            let stream_settings = outbound.stream_settings.get_or_insert_with(|| {
+                crate::v2ray_object::stream_settings::StreamSettingsObject {
+                    network: Some("tcp".to_string()),
+                    ..Default::default()
+                }
+            });
+            let sock_opt = stream_settings
+                .sock_opt
+                .get_or_insert_with(crate::v2ray_object::stream_settings::SockOpt::default);
+            if let Some(domain_strategy) = &global_settings.domain_strategy {
+                sock_opt.domain_strategy = Some(domain_strategy.clone());
+            }
+            if let Some(mark) = global_settings.mark {
+                sock_opt.mark = Some(mark);
             }
+            if let Some(tcp_fast_open) = global_settings.tcp_fast_open {
+                sock_opt.tcp_fast_open = Some(tcp_fast_open);
+            }
+        }
+
+        if let Some(patch) = global_settings
+            .template
+            .as_ref()
+            .and_then(|t| t.outbound.as_ref())
+        {
+            // The template goes underneath, so reapply the node fields over it.
+            let node = serde_json::to_value(&*outbound)?;
+            Template::patch(patch, outbound)?;
+            Template::patch(&node, outbound)?;
+        }
+
+        let mux = outbound.mux.get_or_insert_with(|| MuxObject {
+            concurrency: Some(8),
+            ..MuxObject::default()
+        });
+        if let Some(enabled) = global_settings.use_mux {
+            mux.enabled = enabled;
+        }
+        if let Some(concurrency) = global_settings.mux_concurrency {
+            mux.concurrency = Some(concurrency);
         }
+        Ok(())
+    }
 
-        match self.protocol.as_str() {
-            "vmess" => {
-                outbound.settings.as_mut().unwrap().servers = None;
-                let vnext = outbound
-                    .settings
-                    .as_mut()
-                    .unwrap()
-                    .vnext
-                    .as_mut()
-                    .unwrap()
-                    .get_mut(0)
-                    .unwrap();
-                vnext.address = self.address.clone();
-                vnext.port = crate::v2ray_object::Port::Int(self.port);
-
-                let user = vnext.users.get_mut(0).unwrap();
-                user.id = self.id.clone();
-                user.alter_id = self.alter_id;
-                user.security = Some(self.security.clone());
-                user.level = Some(8);
-
-                outbound.mux = Some(mux);
-
-                outbound.stream_settings = Some(self.gen_bound_stream_settings());
-            }
-            _ => unimplemented!(),
+    fn gen_vnext_settings(&self, user: UserObject) -> VnextSettings {
+        VnextSettings {
+            vnext: vec![ServerObject {
+                address: self.address.clone(),
+                port: Port::Int(self.port),
+                users: vec![user],
+                extra: Default::default(),
+            }],
+            extra: Default::default(),
         }
     }
 
@@ -153,52 +370,187 @@ impl VLink {
         let mut stream_settings =
             crate::v2ray_object::stream_settings::StreamSettingsObject::default();
 
-        stream_settings.network = Some(self.network.clone());
-        stream_settings.security = Some(self.stream_security.clone());
-
-        if let Some(network) = stream_settings.network.as_ref() {
-            match network.as_str() {
-                "kcp" => {
-                    let mut kcp_settings =
-                        crate::v2ray_object::stream_settings::KcpObject::default();
-                    if kcp_settings.header.is_none() {
-                        kcp_settings.header =
-                            Some(crate::v2ray_object::stream_settings::HeaderObject::default());
-                    }
-                    kcp_settings.header.as_mut().unwrap().r#type = Some(self.header_type.clone());
-                    stream_settings.kcp_settings = Some(kcp_settings);
-                }
-                "ws" => {
-                    let mut ws_settings =
-                        crate::v2ray_object::stream_settings::WebSocketObject::default();
-                    let host = self.request_host.trim().to_string();
-                    let path = self.path.trim().to_string();
+        stream_settings.network = Some(self.transport.network().to_string());
+        stream_settings.security = Some(self.stream_security.as_str().to_string());
+
+        match &self.transport {
+            Transport::Tcp {
+                header_type,
+                host,
+                path,
+            } => {
+                if header_type == "http" {
+                    let mut request =
+                        crate::v2ray_object::stream_settings::HttpRequestObject::default();
                     if !host.is_empty() {
-                        if ws_settings.headers.is_none() {
-                            ws_settings.headers = Some(HashMap::new());
-                        }
-                        ws_settings
-                            .headers
-                            .as_mut()
-                            .unwrap()
-                            .insert("host".to_string(), host.clone());
+                        let mut headers = IndexMap::new();
+                        headers.insert(
+                            "Host".to_string(),
+                            crate::v2ray_object::stream_settings::HttpHeaderValueObject::Array(
+                                host.clone(),
+                            ),
+                        );
+                        request.headers = Some(headers);
                     }
                     if !path.is_empty() {
-                        ws_settings.path = Some(path);
+                        request.path = Some(path.clone());
                     }
-                    stream_settings.ws_settings = Some(ws_settings);
-
-                    let mut tls_settings =
-                        crate::v2ray_object::stream_settings::TLSObject::default();
-                    tls_settings.allow_insecure = Some(false);
-                    tls_settings.server_name = Some(host);
-
-                    stream_settings.tls_settings = Some(tls_settings);
+                    stream_settings.tcp_settings =
+                        Some(crate::v2ray_object::stream_settings::TcpObject {
+                            accept_proxy_protocol: None,
+                            header: Some(
+                                crate::v2ray_object::stream_settings::PseudoHeaderObject::Http {
+                                    request: Some(request),
+                                    response: None,
+                                    extra: Default::default(),
+                                },
+                            ),
+                            extra: Default::default(),
+                        });
+                }
+            }
+            Transport::Kcp { header_type } => {
+                let mut kcp_settings = crate::v2ray_object::stream_settings::KcpObject::default();
+                if kcp_settings.header.is_none() {
+                    kcp_settings.header =
+                        Some(crate::v2ray_object::stream_settings::HeaderObject::default());
                 }
-                _ => unimplemented!(),
+                kcp_settings.header.as_mut().unwrap().r#type = Some(header_type.clone());
+                stream_settings.kcp_settings = Some(kcp_settings);
             }
+            Transport::Ws { host, path } => {
+                let mut ws_settings =
+                    crate::v2ray_object::stream_settings::WebSocketObject::default();
+                let host = host.trim().to_string();
+                let path = path.trim().to_string();
+                if !host.is_empty() {
+                    if ws_settings.headers.is_none() {
+                        ws_settings.headers = Some(IndexMap::new());
+                    }
+                    ws_settings
+                        .headers
+                        .as_mut()
+                        .unwrap()
+                        .insert("host".to_string(), host);
+                }
+                if !path.is_empty() {
+                    ws_settings.path = Some(path);
+                }
+                stream_settings.ws_settings = Some(ws_settings);
+            }
+            Transport::H2 { host, path } => {
+                let mut http_settings = crate::v2ray_object::stream_settings::HttpObject::default();
+                if !host.is_empty() {
+                    http_settings.host = Some(host.clone());
+                }
+                if !path.is_empty() {
+                    http_settings.path = Some(path.clone());
+                }
+                stream_settings.http_settings = Some(http_settings);
+            }
+            Transport::Quic {
+                security,
+                key,
+                header_type,
+            } => {
+                stream_settings.quic_settings =
+                    Some(crate::v2ray_object::stream_settings::QuicObject {
+                        security: Some(security.clone()),
+                        key: if key.is_empty() {
+                            None
+                        } else {
+                            Some(key.clone())
+                        },
+                        header: Some(crate::v2ray_object::stream_settings::HeaderObject {
+                            r#type: Some(header_type.clone()),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    });
+            }
+            Transport::Grpc {
+                service_name,
+                multi_mode,
+            } => {
+                stream_settings.grpc_settings =
+                    Some(crate::v2ray_object::stream_settings::GrpcObject {
+                        service_name: Some(service_name.clone()),
+                        multi_mode: if *multi_mode { Some(true) } else { None },
+                        ..Default::default()
+                    });
+            }
+        }
+
+        if self.stream_security == StreamSecurity::Tls {
+            stream_settings.tls_settings = Some(self.gen_tls_settings());
         }
 
         stream_settings
     }
+
+    /// TLS settings from the link, falling back to the host header of the
+    /// transport for the server name.
+    fn gen_tls_settings(&self) -> crate::v2ray_object::stream_settings::TLSObject {
+        let mut tls_settings = crate::v2ray_object::stream_settings::TLSObject::default();
+        let server_name = if !self.sni.is_empty() {
+            Some(self.sni.clone())
+        } else {
+            match &self.transport {
+                Transport::Ws { host, .. } => Some(host.trim().to_string()),
+                Transport::Tcp { host, .. } | Transport::H2 { host, .. } => host.first().cloned(),
+                _ => None,
+            }
+        };
+        tls_settings.server_name = server_name.filter(|name| !name.is_empty());
+        tls_settings.allow_insecure = Some(self.allow_insecure);
+        if !self.alpn.is_empty() {
+            tls_settings.alpn = Some(self.alpn.clone());
+        }
+        if !self.fingerprint.is_empty() {
+            tls_settings.fingerprint = Some(self.fingerprint.clone());
+        }
+        tls_settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mux(global_settings: &GlobalSettings) -> serde_json::Value {
+        let outbound = VLink::default()
+            .gen_outbound("proxy", Some(global_settings))
+            .unwrap();
+        serde_json::to_value(outbound.mux).unwrap()
+    }
+
+    #[test]
+    fn template_mux_survives_unless_set() {
+        let template = Template {
+            outbound: Some(serde_json::json!({"mux": {"enabled": true, "concurrency": 4}})),
+            full: None,
+        };
+        let global_settings = GlobalSettings {
+            template: Some(template),
+            ..GlobalSettings::default()
+        };
+        assert_eq!(
+            mux(&global_settings),
+            serde_json::json!({"enabled": true, "concurrency": 4})
+        );
+
+        let proxy_settings = OutboundSettings {
+            mux: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            mux(&global_settings.with(Some(&proxy_settings))),
+            serde_json::json!({"enabled": false, "concurrency": 4})
+        );
+
+        assert_eq!(
+            mux(&GlobalSettings::default()),
+            serde_json::json!({"enabled": false, "concurrency": 8})
+        );
+    }
 }
@@ -1,47 +1,442 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
+
+/// The protocol of a node together with its credentials.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "protocol", rename_all = "lowercase")]
+pub enum Protocol {
+    Vmess {
+        id: String,
+        alter_id: i32,
+        security: String,
+    },
+    Vless {
+        id: String,
+        encryption: String,
+        #[serde(default)]
+        flow: String,
+    },
+    Trojan {
+        password: String,
+    },
+    Shadowsocks {
+        method: String,
+        password: String,
+        /// SIP003 plugin as `name;opt=value;..`, kept for share links.
+        #[serde(default)]
+        plugin: String,
+    },
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Vmess { .. } => "vmess",
+            Protocol::Vless { .. } => "vless",
+            Protocol::Trojan { .. } => "trojan",
+            Protocol::Shadowsocks { .. } => "shadowsocks",
+        }
+    }
+}
+
+/// The transport of a node together with its parameters.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "network", rename_all = "lowercase")]
+pub enum Transport {
+    Tcp {
+        /// `none`, or `http` to disguise the stream as HTTP requests.
+        #[serde(default = "none")]
+        header_type: String,
+        #[serde(default)]
+        host: Vec<String>,
+        #[serde(default)]
+        path: Vec<String>,
+    },
+    Kcp {
+        header_type: String,
+    },
+    Ws {
+        host: String,
+        path: String,
+    },
+    H2 {
+        host: Vec<String>,
+        path: String,
+    },
+    Quic {
+        /// Renamed so it does not clash with the VMess `security` once both
+        /// are flattened into `VLink`.
+        #[serde(rename = "quic_security")]
+        security: String,
+        key: String,
+        header_type: String,
+    },
+    Grpc {
+        service_name: String,
+        #[serde(default)]
+        multi_mode: bool,
+    },
+}
+
+fn none() -> String {
+    "none".to_string()
+}
+
+fn unmeasured() -> i32 {
+    -1
+}
+
+/// Split a comma separated share link field, dropping empty items.
+pub fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+impl Transport {
+    /// Plain TCP, without header obfuscation.
+    pub fn tcp() -> Self {
+        Transport::Tcp {
+            header_type: none(),
+            host: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    pub fn network(&self) -> &'static str {
+        match self {
+            Transport::Tcp { .. } => "tcp",
+            Transport::Kcp { .. } => "kcp",
+            Transport::Ws { .. } => "ws",
+            Transport::H2 { .. } => "h2",
+            Transport::Quic { .. } => "quic",
+            Transport::Grpc { .. } => "grpc",
+        }
+    }
+
+    /// The transport described by a SIP003 plugin (`name;opt=value;..`),
+    /// of which only `v2ray-plugin` in websocket mode maps onto V2Ray.
+    pub fn from_ss_plugin(plugin: &str) -> Result<(Self, StreamSecurity), String> {
+        let mut opts = plugin.split(';');
+        match opts.next() {
+            Some("v2ray-plugin") => {}
+            Some("") | None => return Ok((Transport::tcp(), StreamSecurity::None)),
+            Some(name) => return Err(format!("unsupported plugin {}", name)),
+        }
+        let mut params = TransportParams {
+            network: "ws".to_string(),
+            ..TransportParams::default()
+        };
+        let mut stream_security = StreamSecurity::None;
+        for opt in opts {
+            match opt.split_once('=') {
+                Some(("mode", "websocket")) => {}
+                Some(("mode", mode)) => return Err(format!("unsupported plugin mode {}", mode)),
+                Some(("host", v)) => params.host = v.to_string(),
+                Some(("path", v)) => params.path = v.to_string(),
+                None if opt == "tls" => stream_security = StreamSecurity::Tls,
+                _ => {}
+            }
+        }
+        Ok((Transport::try_from(params)?, stream_security))
+    }
+}
+
+/// The loosely typed transport fields of share links and provider formats,
+/// following the VMess share convention: QUIC keeps its security in `host`
+/// and its key in `path`, gRPC its service name in `path` and its mode
+/// (`gun` or `multi`) in `header_type`, lists are comma separated.
+#[derive(Debug, Default)]
+pub struct TransportParams {
+    pub network: String,
+    pub header_type: String,
+    pub host: String,
+    pub path: String,
+}
+
+impl TryFrom<TransportParams> for Transport {
+    type Error = String;
+
+    fn try_from(params: TransportParams) -> Result<Self, Self::Error> {
+        let header_type = if params.header_type.is_empty() {
+            none()
+        } else {
+            params.header_type
+        };
+        match params.network.as_str() {
+            "" | "tcp" => match header_type.as_str() {
+                "none" | "http" => Ok(Transport::Tcp {
+                    header_type,
+                    host: split_list(params.host.as_str()),
+                    path: split_list(params.path.as_str()),
+                }),
+                t => Err(format!("unsupported tcp header {}", t)),
+            },
+            "kcp" => Ok(Transport::Kcp { header_type }),
+            "ws" => Ok(Transport::Ws {
+                host: params.host,
+                path: params.path,
+            }),
+            "h2" | "http" => Ok(Transport::H2 {
+                host: split_list(params.host.as_str()),
+                path: params.path,
+            }),
+            "quic" => Ok(Transport::Quic {
+                security: if params.host.is_empty() {
+                    none()
+                } else {
+                    params.host
+                },
+                key: params.path,
+                header_type,
+            }),
+            "grpc" => Ok(Transport::Grpc {
+                service_name: params.path,
+                multi_mode: header_type == "multi",
+            }),
+            network => Err(format!("unsupported network {}", network)),
+        }
+    }
+}
+
+impl From<&Transport> for TransportParams {
+    fn from(transport: &Transport) -> Self {
+        let mut params = TransportParams {
+            network: transport.network().to_string(),
+            ..TransportParams::default()
+        };
+        match transport {
+            Transport::Tcp {
+                header_type,
+                host,
+                path,
+            } => {
+                params.header_type = header_type.clone();
+                params.host = host.join(",");
+                params.path = path.join(",");
+            }
+            Transport::Kcp { header_type } => params.header_type = header_type.clone(),
+            Transport::Ws { host, path } => {
+                params.host = host.clone();
+                params.path = path.clone();
+            }
+            Transport::H2 { host, path } => {
+                params.host = host.join(",");
+                params.path = path.clone();
+            }
+            Transport::Quic {
+                security,
+                key,
+                header_type,
+            } => {
+                params.host = security.clone();
+                params.path = key.clone();
+                params.header_type = header_type.clone();
+            }
+            Transport::Grpc {
+                service_name,
+                multi_mode,
+            } => {
+                params.path = service_name.clone();
+                params.header_type = if *multi_mode { "multi" } else { "gun" }.to_string();
+            }
+        }
+        params
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamSecurity {
+    #[default]
+    None,
+    Tls,
+}
+
+impl StreamSecurity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamSecurity::None => "none",
+            StreamSecurity::Tls => "tls",
+        }
+    }
+}
+
+impl FromStr for StreamSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "none" => Ok(StreamSecurity::None),
+            "tls" => Ok(StreamSecurity::Tls),
+            s => Err(format!("unsupported security {}", s)),
+        }
+    }
+}
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct VLink {
-    pub protocol: String,
+    #[serde(flatten)]
+    pub protocol: Protocol,
     pub address: String,
     pub port: u16,
-    pub id: String,
-    pub alter_id: i32,
-    pub security: String,
-    pub network: String,
     pub remarks: String,
-    pub header_type: String,
-    pub request_host: String,
-    pub path: String,
-    #[serde(alias = "streamSecurity")]
-    pub stream_security: String,
-    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// `(source, remarks)` of duplicates of this node that were collapsed into it.
+    #[serde(default)]
+    pub aliases: Vec<(String, String)>,
+    #[serde(flatten)]
+    pub transport: Transport,
+    #[serde(default, alias = "streamSecurity")]
+    pub stream_security: StreamSecurity,
+    #[serde(default)]
+    pub sni: String,
+    /// Skip verifying the server certificate.
+    #[serde(default)]
+    pub allow_insecure: bool,
+    #[serde(default)]
+    pub alpn: Vec<String>,
+    /// TLS client hello fingerprint to imitate, e.g. `chrome`.
+    #[serde(default)]
+    pub fingerprint: String,
+    #[serde(skip_serializing, default = "unmeasured")]
     pub latency: i32,
 }
 
 impl Default for VLink {
     fn default() -> Self {
         Self {
-            protocol: "vmess".to_string(),
+            protocol: Protocol::Vmess {
+                id: "bf0067d4-831e-4911-b644-9b4582f69671".to_string(),
+                alter_id: 64,
+                security: "auto".to_string(),
+            },
             address: "v2ray.cool".to_string(),
             port: 10086,
-            id: "bf0067d4-831e-4911-b644-9b4582f69671".to_string(),
-            alter_id: 64,
-            security: "".to_string(),
-            network: "tcp".to_string(),
             remarks: "def".to_string(),
-            header_type: "".to_string(),
-            request_host: "".to_string(),
-            path: "".to_string(),
-            stream_security: "".to_string(),
+            source: "".to_string(),
+            tags: BTreeMap::new(),
+            aliases: Vec::new(),
+            transport: Transport::tcp(),
+            stream_security: StreamSecurity::None,
+            sni: "".to_string(),
+            allow_insecure: false,
+            alpn: Vec::new(),
+            fingerprint: "".to_string(),
             latency: -1,
         }
     }
 }
 
+impl VLink {
+    /// Whether `re` matches the remarks of this node or of one of its
+    /// aliases, either on their own or prefixed with the subscription name
+    /// as `source/remarks`.
+    pub fn is_match(&self, re: &regex::Regex) -> bool {
+        std::iter::once((&self.source, &self.remarks))
+            .chain(
+                self.aliases
+                    .iter()
+                    .map(|(source, remarks)| (source, remarks)),
+            )
+            .any(|(source, remarks)| {
+                re.is_match(remarks.as_str())
+                    || re.is_match(format!("{}/{}", source, remarks).as_str())
+            })
+    }
+
+    /// The server this node connects to: protocol, address, port,
+    /// credentials and transport, regardless of its remarks.
+    pub fn identity(&self) -> String {
+        format!(
+            "{:?}@{}:{}?{:?}&security={}&sni={}",
+            self.protocol,
+            self.address.to_lowercase(),
+            self.port,
+            self.transport,
+            self.stream_security.as_str(),
+            self.sni
+        )
+    }
+
+    /// Collapse nodes sharing the same `identity`, keeping the first one
+    /// and recording the remarks and tags of the others on it.
+    pub fn dedup(vlinks: Vec<VLink>) -> Vec<VLink> {
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut v: Vec<VLink> = Vec::with_capacity(vlinks.len());
+        for vlink in vlinks {
+            match index.get(&vlink.identity()) {
+                Some(&i) => {
+                    let first = &mut v[i];
+                    first.aliases.push((vlink.source, vlink.remarks));
+                    first.aliases.extend(vlink.aliases);
+                    for (name, value) in vlink.tags {
+                        first.tags.entry(name).or_insert(value);
+                    }
+                }
+                None => {
+                    index.insert(vlink.identity(), v.len());
+                    v.push(vlink);
+                }
+            }
+        }
+        v
+    }
+
+    /// Apply remark `rules` in order: the named captures of each rule are
+    /// recorded as tags, then the remarks are rewritten with its replacement.
+    pub fn apply_remark_rules(&mut self, rules: &[(regex::Regex, Option<String>)]) {
+        for (re, replace) in rules {
+            if let Some(caps) = re.captures(self.remarks.as_str()) {
+                for name in re.capture_names().flatten() {
+                    if let Some(value) = caps.name(name) {
+                        self.tags
+                            .insert(name.to_string(), value.as_str().trim().to_string());
+                    }
+                }
+            }
+            if let Some(replace) = replace {
+                self.remarks = re
+                    .replace_all(self.remarks.as_str(), replace.as_str())
+                    .trim()
+                    .to_string();
+            }
+        }
+    }
+}
+
 impl fmt::Debug for VLink {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.remarks)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vmess_over_quic_serde_round_trip() {
+        let vlink = VLink {
+            transport: Transport::Quic {
+                security: "aes-128-gcm".to_string(),
+                key: "secret".to_string(),
+                header_type: "none".to_string(),
+            },
+            ..VLink::default()
+        };
+        let json = serde_json::to_string(&vlink).unwrap();
+        assert_eq!(json.matches("\"security\"").count(), 1);
+        let parsed = serde_json::from_str::<VLink>(json.as_str()).unwrap();
+        assert_eq!(parsed.protocol, vlink.protocol);
+        assert_eq!(parsed.transport, vlink.transport);
+    }
+}